@@ -23,7 +23,7 @@ show_help() {
     echo "  start       启动服务"
     echo "  stop        停止服务"
     echo "  restart     重启服务"
-    echo "  reload      重新加载配置"
+    echo "  reload      重新加载 TLS 证书（修改配置请使用 restart）"
     echo "  status      查看服务状态"
     echo "  logs        查看实时日志"
     echo "  logs-tail   查看最近日志"
@@ -46,11 +46,34 @@ check_service() {
     fi
 }
 
+# 服务地址，配置了 TLS 证书时使用 https
+service_url() {
+    local port scheme=http
+    port=$(grep -E "^CHATROOM_PORT=" /etc/default/chatroom-app 2>/dev/null | cut -d'=' -f2)
+    if grep -qE "^TLS_CERT_PATH=." /etc/default/chatroom-app 2>/dev/null; then
+        scheme=https
+    fi
+    echo "$scheme://localhost:${port:-8080}"
+}
+
+# 等待 /readyz 返回 200，最多 30 秒
+wait_ready() {
+    local url
+    url="$(service_url)/readyz"
+    for _ in $(seq 1 30); do
+        if curl -fsk -o /dev/null "$url"; then
+            return 0
+        fi
+        sleep 1
+    done
+    return 1
+}
+
 # 启动服务
 start_service() {
     echo -e "${BLUE}启动服务...${NC}"
     sudo systemctl start "$APP_NAME"
-    if systemctl is-active --quiet "$APP_NAME"; then
+    if systemctl is-active --quiet "$APP_NAME" && wait_ready; then
         echo -e "${GREEN}✓ 服务启动成功${NC}"
     else
         echo -e "${RED}✗ 服务启动失败${NC}"
@@ -70,11 +93,11 @@ stop_service() {
     fi
 }
 
-# 重启服务
+# 重启服务，旧进程会通知所有会话稍后重连并正常关闭连接
 restart_service() {
     echo -e "${BLUE}重启服务...${NC}"
     sudo systemctl restart "$APP_NAME"
-    if systemctl is-active --quiet "$APP_NAME"; then
+    if systemctl is-active --quiet "$APP_NAME" && wait_ready; then
         echo -e "${GREEN}✓ 服务重启成功${NC}"
     else
         echo -e "${RED}✗ 服务重启失败${NC}"
@@ -82,11 +105,11 @@ restart_service() {
     fi
 }
 
-# 重新加载配置
+# 重新加载 TLS 证书，未启用 TLS 时应用会忽略该信号
 reload_service() {
-    echo -e "${BLUE}重新加载配置...${NC}"
+    echo -e "${BLUE}重新加载 TLS 证书...${NC}"
     sudo systemctl reload-or-restart "$APP_NAME"
-    echo -e "${GREEN}✓ 配置已重新加载${NC}"
+    echo -e "${GREEN}✓ 已通知服务重新加载证书${NC}"
 }
 
 # 查看服务状态
@@ -189,20 +212,20 @@ health_check() {
         return 1
     fi
     
-    # 检查端口
-    PORT=$(grep -E "^CHATROOM_PORT=" /etc/default/chatroom-app 2>/dev/null | cut -d'=' -f2 || echo "8080")
-    if netstat -tuln | grep -q ":$PORT "; then
-        echo -e "端口监听: ${GREEN}✓ 端口 $PORT 正在监听${NC}"
+    # 存活检查
+    URL=$(service_url)
+    if curl -fsk -o /dev/null "$URL/healthz"; then
+        echo -e "存活检查: ${GREEN}✓ 服务正常响应${NC}"
     else
-        echo -e "端口监听: ${RED}✗ 端口 $PORT 未监听${NC}"
+        echo -e "存活检查: ${RED}✗ $URL/healthz 无响应${NC}"
         return 1
     fi
     
-    # 检查HTTP响应
-    if curl -s -o /dev/null -w "%{http_code}" "http://localhost:$PORT" | grep -q "200"; then
-        echo -e "HTTP响应: ${GREEN}✓ 服务正常响应${NC}"
+    # 就绪检查，正在退出时返回 503
+    if curl -fsk -o /dev/null "$URL/readyz"; then
+        echo -e "就绪检查: ${GREEN}✓ 可以接受新连接${NC}"
     else
-        echo -e "HTTP响应: ${YELLOW}⚠ 服务可能未完全启动${NC}"
+        echo -e "就绪检查: ${YELLOW}⚠ 服务未就绪或正在退出${NC}"
     fi
     
     echo -e "${GREEN}✓ 健康检查完成${NC}"
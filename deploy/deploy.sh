@@ -86,6 +86,7 @@ create_directories() {
     
     mkdir -p "$APP_DIR"
     mkdir -p "$APP_DIR/logs"
+    mkdir -p "$APP_DIR/data"
     mkdir -p "$APP_DIR/static"
     mkdir -p "$APP_DIR/target/release"
     
@@ -140,14 +141,37 @@ install_service() {
     log_info "systemd 服务安装完成"
 }
 
-# 启动服务
+# 服务地址，配置了 TLS 证书时使用 https
+service_url() {
+    local port scheme=http
+    port=$(grep -E "^CHATROOM_PORT=" /etc/default/chatroom-app 2>/dev/null | cut -d'=' -f2)
+    if grep -qE "^TLS_CERT_PATH=." /etc/default/chatroom-app 2>/dev/null; then
+        scheme=https
+    fi
+    echo "$scheme://localhost:${port:-8080}"
+}
+
+# 等待 /readyz 返回 200，最多 30 秒
+wait_ready() {
+    local url
+    url="$(service_url)/readyz"
+    for _ in $(seq 1 30); do
+        if curl -fsk -o /dev/null "$url"; then
+            return 0
+        fi
+        sleep 1
+    done
+    return 1
+}
+
+# 启动服务，已在运行时重启，旧进程会通知所有会话稍后重连
 start_service() {
     log_info "启动服务..."
     
-    systemctl start "$APP_NAME"
+    systemctl restart "$APP_NAME"
     
-    # 检查服务状态
-    if systemctl is-active --quiet "$APP_NAME"; then
+    # 检查服务状态，等待就绪检查通过
+    if systemctl is-active --quiet "$APP_NAME" && wait_ready; then
         log_info "服务启动成功"
         systemctl status "$APP_NAME" --no-pager
     else
@@ -1,122 +1,395 @@
-use std::collections::HashMap;
+use std::collections::{HashMap, VecDeque};
+use std::fmt;
+use std::sync::{mpsc, Arc};
 use uuid::Uuid;
 use serde::{Deserialize, Serialize};
-use actix::Addr;
-use crate::websocket::WebSocketSession;
+use argon2::{Argon2, PasswordHasher, PasswordVerifier};
+use argon2::password_hash::{rand_core::OsRng, PasswordHash, SaltString};
+use actix_web::web;
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use base64::Engine;
+use rand::RngCore;
+use sha2::{Digest, Sha256};
+use subtle::ConstantTimeEq;
+use crate::websocket::{ChatMessage, WebSocketManager};
+use crate::store::{RoomStore, StoreWriter};
+use crate::error::ChatError;
+use crate::config::RoomsConfig;
+
+// Argon2id 哈希后的房间密码（PHC 字符串，内含每个房间独立的盐），Debug 输出时隐藏
+#[derive(Clone, PartialEq)]
+pub struct HashedPassword(String);
+
+impl HashedPassword {
+    pub fn new(password: &str) -> Result<Self, ChatError> {
+        let salt = SaltString::generate(&mut OsRng);
+        Argon2::default()
+            .hash_password(password.as_bytes(), &salt)
+            .map(|hash| Self(hash.to_string()))
+            .map_err(|e| ChatError::Internal(format!("密码哈希失败: {}", e)))
+    }
+
+    pub fn from_phc(hash: String) -> Self {
+        Self(hash)
+    }
+
+    pub fn as_phc(&self) -> &str {
+        &self.0
+    }
+
+    // 由 argon2 完成常量时间比较
+    pub fn verify(&self, password: &str) -> bool {
+        match PasswordHash::new(&self.0) {
+            Ok(hash) => Argon2::default().verify_password(password.as_bytes(), &hash).is_ok(),
+            Err(_) => false,
+        }
+    }
+
+    // 哈希和校验每次需要数十毫秒，处理请求时改用下面两个方法在阻塞线程池中执行，调用时不要持有管理器的锁
+    pub async fn hash(password: String) -> Result<Self, ChatError> {
+        web::block(move || Self::new(&password))
+            .await
+            .map_err(|e| ChatError::Internal(format!("密码哈希失败: {}", e)))?
+    }
+
+    pub async fn check(self, password: String) -> Result<bool, ChatError> {
+        web::block(move || self.verify(&password))
+            .await
+            .map_err(|e| ChatError::Internal(format!("密码校验失败: {}", e)))
+    }
+}
+
+impl fmt::Debug for HashedPassword {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("HashedPassword(..)")
+    }
+}
+
+// 房主令牌，只保存 SHA-256 摘要，明文仅在创建房间时返回一次
+#[derive(Clone, Default)]
+pub struct OwnerToken([u8; 32]);
+
+impl OwnerToken {
+    pub fn generate() -> (Self, String) {
+        let mut bytes = [0u8; 32];
+        rand::thread_rng().fill_bytes(&mut bytes);
+        let token = URL_SAFE_NO_PAD.encode(bytes);
+        (Self(Sha256::digest(token.as_bytes()).into()), token)
+    }
+
+    pub fn from_digest(digest: &[u8]) -> Self {
+        let mut bytes = [0u8; 32];
+        if digest.len() == bytes.len() {
+            bytes.copy_from_slice(digest);
+        }
+        Self(bytes)
+    }
+
+    pub fn digest(&self) -> &[u8; 32] {
+        &self.0
+    }
+
+    pub fn verify(&self, token: &str) -> bool {
+        let digest: [u8; 32] = Sha256::digest(token.as_bytes()).into();
+        self.0.ct_eq(&digest).into()
+    }
+}
+
+impl fmt::Debug for OwnerToken {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("OwnerToken(..)")
+    }
+}
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct ChatRoom {
     pub id: String,
     pub name: String,
-    pub password: Option<String>,
-    pub created_at: chrono::DateTime<chrono::Utc>,
     #[serde(skip)]
-    pub sessions: HashMap<String, Addr<WebSocketSession>>,
+    pub password: Option<HashedPassword>,
+    #[serde(skip)]
+    pub owner_token: OwnerToken,
+    // 是否在服务器端保存消息历史
+    pub history_enabled: bool,
+    // 同时在房间内的最大人数，包括断线等待恢复的成员
+    pub max_members: usize,
+    pub created_at: chrono::DateTime<chrono::Utc>,
+    // 最后一个用户离开的时间，用于回收空闲房间
+    pub last_active_at: chrono::DateTime<chrono::Utc>,
 }
 
 impl ChatRoom {
-    pub fn new(name: String, password: Option<String>) -> Self {
+    pub fn new(name: String, password: Option<HashedPassword>, owner_token: OwnerToken, history_enabled: bool, max_members: usize) -> Self {
+        let now = chrono::Utc::now();
         Self {
             id: Uuid::new_v4().to_string(),
             name,
             password,
-            created_at: chrono::Utc::now(),
-            sessions: HashMap::new(),
+            owner_token,
+            history_enabled,
+            max_members,
+            created_at: now,
+            last_active_at: now,
         }
     }
 
-    pub fn add_session(&mut self, user_id: String, addr: Addr<WebSocketSession>) {
-        self.sessions.insert(user_id, addr);
-    }
-
-    pub fn remove_session(&mut self, user_id: &str) {
-        self.sessions.remove(user_id);
-    }
-
     pub fn has_password(&self) -> bool {
         self.password.is_some()
     }
 
-    pub fn verify_password(&self, password: &str) -> bool {
-        match &self.password {
-            Some(room_password) => room_password == password,
-            None => true,
-        }
+    pub fn is_owner(&self, token: &str) -> bool {
+        self.owner_token.verify(token)
     }
 
-    pub fn update_password(&mut self, new_password: String) -> Result<(), String> {
-        if new_password.trim().is_empty() {
-            return Err("密码不能为空".to_string());
+    pub fn rename(&mut self, name: String) -> Result<(), ChatError> {
+        if name.trim().is_empty() {
+            return Err(ChatError::RoomNameEmpty);
         }
-        self.password = Some(new_password);
+        self.name = name;
         Ok(())
     }
+
+    // 生成新的房主令牌，旧令牌立即失效
+    pub fn rotate_owner_token(&mut self) -> String {
+        let (owner_token, token) = OwnerToken::generate();
+        self.owner_token = owner_token;
+        token
+    }
+
+    // 传入 None 表示移除密码，房间变为公开
+    pub fn update_password(&mut self, new_password: Option<HashedPassword>) {
+        self.password = new_password;
+    }
 }
 
 #[derive(Debug)]
 pub struct ChatRoomManager {
     rooms: HashMap<String, ChatRoom>,
+    // 开启历史的房间最近的消息，容量为 history_size
+    history: HashMap<String, VecDeque<ChatMessage>>,
+    // 序号不小于该值的消息都在 history 中，更早的只在存储中
+    history_start: HashMap<String, u64>,
+    history_size: usize,
+    // 新房间默认且允许设置的最大人数
+    max_members: usize,
+    store: Arc<dyn RoomStore>,
+    // 房间和消息的写入在后台线程中执行
+    writer: StoreWriter,
 }
 
 impl ChatRoomManager {
-    pub fn new() -> Self {
-        Self {
-            rooms: HashMap::new(),
+    // 从存储中加载已有房间及其最近的消息
+    pub fn with_store(store: Box<dyn RoomStore>, config: &RoomsConfig) -> Result<Self, String> {
+        let mut rooms = HashMap::new();
+        let mut history = HashMap::new();
+        let mut history_start = HashMap::new();
+        let now = chrono::Utc::now();
+        for mut room in store.load_rooms()? {
+            // 重启前仍有成员在线的房间没有更新过 last_active_at，空闲时间从启动时重新计算
+            room.last_active_at = now;
+            if room.history_enabled {
+                let messages = store.load_messages(&room.id, None, config.history_size)?;
+                // 未装满说明存储中没有更早的消息
+                if messages.len() >= config.history_size {
+                    history_start.insert(room.id.clone(), messages.first().map_or(0, |message| message.seq));
+                }
+                history.insert(room.id.clone(), messages.into());
+            }
+            // 旧版本创建的房间没有人数上限，使用当前配置
+            if room.max_members == 0 {
+                room.max_members = config.max_members;
+            }
+            rooms.insert(room.id.clone(), room);
         }
+        let store: Arc<dyn RoomStore> = Arc::from(store);
+        let writer = StoreWriter::spawn(store.clone())?;
+        Ok(Self { rooms, history, history_start, history_size: config.history_size, max_members: config.max_members, store, writer })
     }
 
-    pub fn create_room(&mut self, name: String, password: Option<String>) -> Result<String, String> {
+    fn persist(&self, room_id: &str) {
+        if let Some(room) = self.rooms.get(room_id) {
+            self.writer.save_room(room);
+        }
+    }
+
+    // 返回房间ID和房主令牌，未指定人数上限时使用配置的默认值；密码由调用方在锁外哈希
+    pub fn create_room(&mut self, name: String, password: Option<HashedPassword>, history_enabled: bool, max_members: Option<usize>) -> Result<(String, String), ChatError> {
         if name.trim().is_empty() {
-            return Err("聊天室名称不能为空".to_string());
+            return Err(ChatError::RoomNameEmpty);
+        }
+        let max_members = max_members.unwrap_or(self.max_members);
+        if max_members == 0 || max_members > self.max_members {
+            return Err(ChatError::InvalidMaxMembers(self.max_members));
         }
 
-        let room = ChatRoom::new(name, password);
+        let (owner_token, token) = OwnerToken::generate();
+        let room = ChatRoom::new(name, password, owner_token, history_enabled, max_members);
         let room_id = room.id.clone();
+        self.writer.save_room(&room);
+        if history_enabled {
+            self.history.insert(room_id.clone(), VecDeque::new());
+        }
         self.rooms.insert(room_id.clone(), room);
-        Ok(room_id)
+        Ok((room_id, token))
+    }
+
+    pub fn room_count(&self) -> usize {
+        self.rooms.len()
     }
 
     pub fn get_room(&self, room_id: &str) -> Option<&ChatRoom> {
         self.rooms.get(room_id)
     }
 
-    pub fn get_room_mut(&mut self, room_id: &str) -> Option<&mut ChatRoom> {
-        self.rooms.get_mut(room_id)
+    // 房间的密码哈希，没有密码时为 None；复制出来后在锁外校验
+    pub fn room_password(&self, room_id: &str) -> Result<Option<HashedPassword>, ChatError> {
+        self.rooms.get(room_id)
+            .map(|room| room.password.clone())
+            .ok_or(ChatError::RoomNotFound)
     }
 
-    pub fn join_room(&mut self, room_id: &str, user_id: String, password: Option<String>, addr: Addr<WebSocketSession>) -> Result<(), String> {
+    pub fn update_room_password(&mut self, room_id: &str, new_password: Option<HashedPassword>) -> Result<(), ChatError> {
         let room = self.rooms.get_mut(room_id)
-            .ok_or("聊天室不存在")?;
-
-        // 验证密码
-        if let Some(room_password) = &room.password {
-            match password {
-                Some(provided_password) => {
-                    if room_password != &provided_password {
-                        return Err("密码错误".to_string());
-                    }
-                }
-                None => return Err("该聊天室需要密码".to_string()),
-            }
-        }
+            .ok_or(ChatError::RoomNotFound)?;
+        
+        room.update_password(new_password);
+        self.persist(room_id);
+        Ok(())
+    }
 
-        room.add_session(user_id, addr);
+    pub fn rename_room(&mut self, room_id: &str, name: String) -> Result<(), ChatError> {
+        let room = self.rooms.get_mut(room_id)
+            .ok_or(ChatError::RoomNotFound)?;
+
+        room.rename(name)?;
+        self.persist(room_id);
         Ok(())
     }
 
-    pub fn update_room_password(&mut self, room_id: &str, new_password: String) -> Result<(), String> {
+    pub fn rotate_owner_token(&mut self, room_id: &str) -> Result<String, ChatError> {
         let room = self.rooms.get_mut(room_id)
-            .ok_or("聊天室不存在")?;
-        
-        room.update_password(new_password)
+            .ok_or(ChatError::RoomNotFound)?;
+
+        let token = room.rotate_owner_token();
+        self.persist(room_id);
+        Ok(token)
     }
 
-    pub fn list_rooms(&self) -> Vec<RoomInfo> {
+    pub fn delete_room(&mut self, room_id: &str) -> Option<ChatRoom> {
+        self.writer.delete_room(room_id);
+        self.history.remove(room_id);
+        self.history_start.remove(room_id);
+        self.rooms.remove(room_id)
+    }
+
+    pub fn touch_room(&mut self, room_id: &str) {
+        if let Some(room) = self.rooms.get_mut(room_id) {
+            room.last_active_at = chrono::Utc::now();
+            self.persist(room_id);
+        }
+    }
+
+    // 只记录开启了历史的房间，超出容量时丢弃最旧的消息
+    pub fn record_message(&mut self, room_id: &str, message: ChatMessage) {
+        let Some(history) = self.history.get_mut(room_id) else {
+            return;
+        };
+
+        self.writer.save_message(room_id, &message);
+        history.push_back(message);
+        while history.len() > self.history_size {
+            if let Some(evicted) = history.pop_front() {
+                self.history_start.insert(room_id.to_string(), evicted.seq + 1);
+            }
+        }
+    }
+
+    pub fn find_message(&self, room_id: &str, message_id: &str) -> Option<&ChatMessage> {
+        self.history.get(room_id)?.iter().find(|message| message.id == message_id)
+    }
+
+    // 编辑后的消息同时写回存储
+    pub fn update_message(&mut self, room_id: &str, message: &ChatMessage) {
+        let Some(history) = self.history.get_mut(room_id) else {
+            return;
+        };
+
+        if let Some(recorded) = history.iter_mut().find(|recorded| recorded.id == message.id) {
+            *recorded = message.clone();
+        }
+        self.writer.update_message(room_id, message);
+    }
+
+    pub fn remove_message(&mut self, room_id: &str, message: &ChatMessage) {
+        let Some(history) = self.history.get_mut(room_id) else {
+            return;
+        };
+
+        history.retain(|recorded| recorded.id != message.id);
+        self.writer.delete_message(room_id, message.seq);
+    }
+
+    // 退出前等待后台线程写完已提交的消息
+    pub fn flush_store(&self) -> mpsc::Receiver<()> {
+        self.writer.flush()
+    }
+
+    // 未开启历史的房间返回 None
+    pub fn recent_messages(&self, room_id: &str) -> Option<Vec<ChatMessage>> {
+        self.history.get(room_id).map(|history| history.iter().cloned().collect())
+    }
+
+    // 开启历史的房间最后分配的序号，启动时用于恢复序号计数
+    pub fn last_sequences(&self) -> Result<Vec<(String, u64)>, String> {
+        self.store.load_sequences()
+    }
+
+    // 分页获取序号小于 before 的最多 limit 条消息，按序号升序；持久化存储时可以翻到内存之外的更早消息。
+    // 存储的写入在后台排队，内存中覆盖的部分以内存为准，只有更早的消息才从存储读取
+    pub fn messages_before(&self, room_id: &str, before: Option<u64>, limit: usize) -> Result<Vec<ChatMessage>, ChatError> {
+        let history = self.history.get(room_id)
+            .ok_or(ChatError::HistoryDisabled)?;
+
+        let mut messages: Vec<ChatMessage> = history.iter()
+            .filter(|message| before.is_none_or(|before| message.seq < before))
+            .cloned()
+            .collect();
+        let skip = messages.len().saturating_sub(limit);
+        let mut messages = messages.split_off(skip);
+
+        let start = self.history_start.get(room_id).copied().unwrap_or(0);
+        if messages.len() < limit && start > 1 && self.store.is_persistent() {
+            let older_than = before.map_or(start, |before| before.min(start));
+            let mut older = self.store.load_messages(room_id, Some(older_than), limit - messages.len())
+                .map_err(ChatError::Internal)?;
+            older.append(&mut messages);
+            messages = older;
+        }
+        Ok(messages)
+    }
+
+    // 删除没有在线用户且空闲超过 ttl 的房间，返回被删除的房间ID
+    pub fn reap_idle_rooms(&mut self, ws_manager: &WebSocketManager, ttl: chrono::Duration) -> Vec<String> {
+        let now = chrono::Utc::now();
+        let idle: Vec<String> = self.rooms.values()
+            .filter(|room| ws_manager.user_count(&room.id) == 0 && now - room.last_active_at > ttl)
+            .map(|room| room.id.clone())
+            .collect();
+
+        for room_id in &idle {
+            self.delete_room(room_id);
+        }
+        idle
+    }
+
+    pub fn list_rooms(&self, ws_manager: &WebSocketManager) -> Vec<RoomInfo> {
         self.rooms.values().map(|room| RoomInfo {
             id: room.id.clone(),
             name: room.name.clone(),
             has_password: room.has_password(),
-            user_count: room.sessions.len(),
+            history_enabled: room.history_enabled,
+            user_count: ws_manager.user_count(&room.id),
+            max_members: room.max_members,
             created_at: room.created_at,
         }).collect()
     }
@@ -127,6 +400,144 @@ pub struct RoomInfo {
     pub id: String,
     pub name: String,
     pub has_password: bool,
+    pub history_enabled: bool,
     pub user_count: usize,
+    pub max_members: usize,
     pub created_at: chrono::DateTime<chrono::Utc>,
-}
\ No newline at end of file
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::metrics::Metrics;
+    use crate::store::{MemoryRoomStore, SqliteRoomStore};
+
+    fn message(seq: u64) -> ChatMessage {
+        ChatMessage {
+            id: format!("message-{}", seq),
+            seq,
+            content: format!("m{}", seq),
+            username: "alice".to_string(),
+            timestamp: chrono::Utc::now(),
+            user_id: "user".to_string(),
+            edited_at: None,
+            reply_to: None,
+            reactions: Vec::new(),
+        }
+    }
+
+    fn seqs(messages: &[ChatMessage]) -> Vec<u64> {
+        messages.iter().map(|message| message.seq).collect()
+    }
+
+    fn history_room(store: Box<dyn RoomStore>, history_size: usize, count: u64) -> (ChatRoomManager, String) {
+        let config = RoomsConfig { history_size, ..RoomsConfig::default() };
+        let mut manager = ChatRoomManager::with_store(store, &config).unwrap();
+        let (room_id, _) = manager.create_room("room".to_string(), None, true, None).unwrap();
+        for seq in 1..=count {
+            manager.record_message(&room_id, message(seq));
+        }
+        (manager, room_id)
+    }
+
+    #[test]
+    fn verifies_hashed_password() {
+        let password = HashedPassword::new("secret").unwrap();
+        assert!(password.as_phc().starts_with("$argon2id$"));
+        assert!(password.verify("secret"));
+        assert!(!password.verify("Secret"));
+        assert!(!password.verify(""));
+        // 同一密码每次使用不同的盐
+        assert_ne!(password, HashedPassword::new("secret").unwrap());
+        assert!(HashedPassword::from_phc(password.as_phc().to_string()).verify("secret"));
+        assert!(!HashedPassword::from_phc("secret".to_string()).verify("secret"));
+    }
+
+    #[test]
+    fn never_exposes_password_hash() {
+        let password = HashedPassword::new("secret").unwrap();
+        assert_eq!(format!("{:?}", password), "HashedPassword(..)");
+
+        let (owner_token, _) = OwnerToken::generate();
+        let room = ChatRoom::new("room".to_string(), Some(password.clone()), owner_token, false, 10);
+        let debug = format!("{:?}", room);
+        let json = serde_json::to_string(&room).unwrap();
+        for output in [debug, json] {
+            assert!(!output.contains(password.as_phc()), "{}", output);
+            assert!(!output.contains("argon2"), "{}", output);
+        }
+    }
+
+    #[test]
+    fn verifies_owner_token() {
+        let (owner_token, token) = OwnerToken::generate();
+        assert!(owner_token.verify(&token));
+        assert!(!owner_token.verify(&token[1..]));
+        assert!(!owner_token.verify(""));
+        assert!(!OwnerToken::generate().0.verify(&token));
+        // 重启后从保存的摘要恢复，长度不对的摘要不匹配任何令牌
+        assert!(OwnerToken::from_digest(owner_token.digest()).verify(&token));
+        assert!(!OwnerToken::from_digest(&owner_token.digest()[1..]).verify(&token));
+        assert_eq!(format!("{:?}", owner_token), "OwnerToken(..)");
+    }
+
+    #[actix_web::test]
+    async fn hashes_and_checks_off_thread() {
+        let password = HashedPassword::hash("secret".to_string()).await.unwrap();
+        assert!(password.clone().check("secret".to_string()).await.unwrap());
+        assert!(!password.check("wrong".to_string()).await.unwrap());
+    }
+
+    #[test]
+    fn pages_in_memory_history() {
+        let (manager, room_id) = history_room(Box::new(MemoryRoomStore), 3, 5);
+        assert_eq!(seqs(&manager.messages_before(&room_id, None, 10).unwrap()), [3, 4, 5]);
+        assert_eq!(seqs(&manager.messages_before(&room_id, None, 2).unwrap()), [4, 5]);
+        assert_eq!(seqs(&manager.messages_before(&room_id, Some(5), 1).unwrap()), [4]);
+        assert!(manager.messages_before(&room_id, Some(3), 10).unwrap().is_empty());
+    }
+
+    #[test]
+    fn pages_recent_messages_from_memory_and_older_from_store() {
+        let (mut manager, room_id) = history_room(Box::new(SqliteRoomStore::open(":memory:").unwrap()), 3, 5);
+        let mut edited = message(5);
+        edited.content = "edited".to_string();
+        manager.update_message(&room_id, &edited);
+        manager.remove_message(&room_id, &message(4));
+
+        // 内存中的部分不依赖后台写入是否完成
+        let recent = manager.messages_before(&room_id, None, 2).unwrap();
+        assert_eq!(seqs(&recent), [3, 5]);
+        assert_eq!(recent[1].content, "edited");
+
+        manager.flush_store().recv().unwrap();
+        assert_eq!(seqs(&manager.messages_before(&room_id, None, 10).unwrap()), [1, 2, 3, 5]);
+        assert_eq!(seqs(&manager.messages_before(&room_id, Some(3), 10).unwrap()), [1, 2]);
+        assert_eq!(seqs(&manager.messages_before(&room_id, Some(5), 2).unwrap()), [2, 3]);
+    }
+
+    #[test]
+    fn messages_before_requires_history() {
+        let mut manager = ChatRoomManager::with_store(Box::new(MemoryRoomStore), &RoomsConfig::default()).unwrap();
+        let (room_id, _) = manager.create_room("room".to_string(), None, false, None).unwrap();
+        assert!(matches!(manager.messages_before(&room_id, None, 10), Err(ChatError::HistoryDisabled)));
+    }
+
+    #[test]
+    fn loaded_rooms_are_not_reaped_right_away() {
+        let store = SqliteRoomStore::open(":memory:").unwrap();
+        let mut room = ChatRoom::new("room".to_string(), None, OwnerToken::generate().0, true, 10);
+        room.last_active_at = chrono::Utc::now() - chrono::Duration::days(1);
+        store.save_room(&room).unwrap();
+
+        let mut manager = ChatRoomManager::with_store(Box::new(store), &RoomsConfig::default()).unwrap();
+        let ws_manager = WebSocketManager::new(Arc::new(Metrics::new()));
+        let ttl = chrono::Duration::seconds(5);
+        assert!(manager.reap_idle_rooms(&ws_manager, ttl).is_empty());
+        assert!(manager.get_room(&room.id).is_some());
+
+        // 启动后仍然空闲超过 ttl 才会回收
+        manager.rooms.get_mut(&room.id).unwrap().last_active_at = chrono::Utc::now() - ttl * 2;
+        assert_eq!(manager.reap_idle_rooms(&ws_manager, ttl), vec![room.id.clone()]);
+        assert!(manager.get_room(&room.id).is_none());
+    }
+}
@@ -0,0 +1,582 @@
+use clap::Parser;
+use serde::Deserialize;
+use std::fmt::{self, Display};
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::time::Duration;
+use crate::ratelimit::RateLimit;
+
+// 命令行参数，优先级高于环境变量，环境变量高于配置文件
+#[derive(Debug, Parser)]
+#[command(version, about = "聊天室服务器")]
+struct Cli {
+    /// TOML 配置文件路径，也可以通过 CHATROOM_CONFIG 环境变量指定
+    #[arg(short, long)]
+    config: Option<PathBuf>,
+    /// 监听地址
+    #[arg(long)]
+    host: Option<String>,
+    /// 监听端口
+    #[arg(short, long)]
+    port: Option<u16>,
+    /// 工作线程数，默认与 CPU 核数相同
+    #[arg(long)]
+    workers: Option<usize>,
+    /// WebSocket 连接总数上限
+    #[arg(long)]
+    max_connections: Option<usize>,
+    /// 每个客户端 IP 同时打开的 WebSocket 连接数上限
+    #[arg(long)]
+    max_connections_per_ip: Option<usize>,
+    /// 收到退出信号后等待会话关闭的最长时间（秒）
+    #[arg(long)]
+    shutdown_timeout: Option<u64>,
+    /// 心跳检测间隔（秒）
+    #[arg(long)]
+    heartbeat_interval: Option<u64>,
+    /// 客户端无响应多久后断开（秒）
+    #[arg(long)]
+    client_timeout: Option<u64>,
+    /// 存储后端：memory 或 sqlite
+    #[arg(long)]
+    store: Option<StoreBackend>,
+    /// SQLite 数据库文件路径
+    #[arg(long)]
+    store_path: Option<String>,
+    /// TLS 证书链文件（PEM），与 --tls-key 同时设置时启用 HTTPS/WSS
+    #[arg(long)]
+    tls_cert: Option<PathBuf>,
+    /// TLS 私钥文件（PEM）
+    #[arg(long)]
+    tls_key: Option<PathBuf>,
+    /// 提供 Prometheus /metrics 接口
+    #[arg(long)]
+    enable_metrics: bool,
+    /// 在单独的端口上提供 /metrics，默认与聊天服务共用端口
+    #[arg(long)]
+    metrics_port: Option<u16>,
+    /// 日志格式：text 或 json
+    #[arg(long)]
+    log_format: Option<LogFormat>,
+    /// 入场券签名密钥，会出现在进程列表中，建议改用环境变量或配置文件
+    #[arg(long)]
+    ticket_secret: Option<String>,
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    pub server: ServerConfig,
+    pub websocket: WebSocketConfig,
+    pub rooms: RoomsConfig,
+    pub limits: LimitsConfig,
+    pub rate_limit: RateLimitConfig,
+    pub store: StoreConfig,
+    pub tls: TlsConfig,
+    pub metrics: MetricsConfig,
+    pub log: LogConfig,
+    pub ticket: TicketConfig,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ServerConfig {
+    pub host: String,
+    pub port: u16,
+    // 未设置时使用 actix-web 的默认值（CPU 核数）
+    pub workers: Option<usize>,
+    // 同时打开的 WebSocket 连接总数和每个客户端 IP 的连接数上限
+    pub max_connections: usize,
+    pub max_connections_per_ip: usize,
+    // 收到退出信号后等待会话关闭的最长秒数
+    pub shutdown_timeout: u64,
+}
+
+impl Default for ServerConfig {
+    fn default() -> Self {
+        Self {
+            host: "0.0.0.0".to_string(),
+            port: 8080,
+            workers: None,
+            max_connections: 1000,
+            max_connections_per_ip: 20,
+            shutdown_timeout: 10,
+        }
+    }
+}
+
+// 心跳参数，单位为秒
+#[derive(Debug, Clone, Copy, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct WebSocketConfig {
+    pub heartbeat_interval: u64,
+    pub client_timeout: u64,
+    // 服务器重启时建议客户端重连的最短等待毫秒数，每个会话另加随机抖动
+    pub reconnect_after_ms: u64,
+}
+
+impl Default for WebSocketConfig {
+    fn default() -> Self {
+        Self {
+            heartbeat_interval: 5,
+            client_timeout: 10,
+            reconnect_after_ms: 3000,
+        }
+    }
+}
+
+impl ServerConfig {
+    pub fn shutdown_timeout(&self) -> Duration {
+        Duration::from_secs(self.shutdown_timeout)
+    }
+}
+
+impl WebSocketConfig {
+    pub fn heartbeat_interval(&self) -> Duration {
+        Duration::from_secs(self.heartbeat_interval)
+    }
+
+    pub fn client_timeout(&self) -> Duration {
+        Duration::from_secs(self.client_timeout)
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct RoomsConfig {
+    // 开启历史的房间在内存中保留的消息条数
+    pub history_size: usize,
+    // 最后一个用户离开后空闲房间的保留秒数
+    pub idle_ttl: u64,
+    // 创建房间时未指定人数上限时使用的值，也是可指定的最大值
+    pub max_members: usize,
+}
+
+impl RoomsConfig {
+    // 超出 chrono 可表示的范围时返回 None
+    pub fn idle_ttl(&self) -> Option<chrono::Duration> {
+        i64::try_from(self.idle_ttl).ok().and_then(chrono::Duration::try_seconds)
+    }
+}
+
+impl Default for RoomsConfig {
+    fn default() -> Self {
+        Self {
+            history_size: 100,
+            idle_ttl: 3600,
+            max_members: 100,
+        }
+    }
+}
+
+// 消息内容和昵称的最大字符数
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct LimitsConfig {
+    pub max_message_length: usize,
+    pub max_username_length: usize,
+}
+
+impl Default for LimitsConfig {
+    fn default() -> Self {
+        Self {
+            max_message_length: 500,
+            max_username_length: 20,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct RateLimitConfig {
+    pub chat: RateLimit,
+    pub chat_max_violations: u32,
+    pub api: RateLimit,
+    pub room_create: RateLimit,
+    pub trust_proxy: bool,
+}
+
+impl Default for RateLimitConfig {
+    fn default() -> Self {
+        Self {
+            chat: RateLimit { rate: 5.0, burst: 10.0 },
+            chat_max_violations: 20,
+            api: RateLimit { rate: 10.0, burst: 30.0 },
+            room_create: RateLimit { rate: 0.1, burst: 5.0 },
+            trust_proxy: false,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum StoreBackend {
+    Memory,
+    Sqlite,
+}
+
+impl FromStr for StoreBackend {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value {
+            "memory" => Ok(Self::Memory),
+            "sqlite" => Ok(Self::Sqlite),
+            other => Err(format!("未知的存储后端: {}", other)),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct StoreConfig {
+    pub backend: StoreBackend,
+    pub path: String,
+}
+
+impl Default for StoreConfig {
+    fn default() -> Self {
+        Self {
+            backend: StoreBackend::Memory,
+            path: "chatroom.db".to_string(),
+        }
+    }
+}
+
+// 证书和私钥同时设置时直接提供 HTTPS/WSS
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct TlsConfig {
+    pub cert_path: Option<PathBuf>,
+    pub key_path: Option<PathBuf>,
+}
+
+impl TlsConfig {
+    pub fn paths(&self) -> Option<(&Path, &Path)> {
+        Some((self.cert_path.as_deref()?, self.key_path.as_deref()?))
+    }
+}
+
+// 未设置 port 时 /metrics 与聊天服务共用端口
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct MetricsConfig {
+    pub enabled: bool,
+    pub port: Option<u16>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum LogFormat {
+    Text,
+    Json,
+}
+
+impl FromStr for LogFormat {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value {
+            "text" => Ok(Self::Text),
+            "json" => Ok(Self::Json),
+            other => Err(format!("未知的日志格式: {}", other)),
+        }
+    }
+}
+
+// 日志级别仍由 RUST_LOG 控制
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct LogConfig {
+    pub format: LogFormat,
+    // 是否在日志中记录聊天、私信和编辑的内容，默认只记录长度
+    pub message_content: bool,
+}
+
+impl Default for LogConfig {
+    fn default() -> Self {
+        Self {
+            format: LogFormat::Text,
+            message_content: false,
+        }
+    }
+}
+
+// 入场券签名密钥的最短字节数
+const MIN_TICKET_SECRET_LEN: usize = 32;
+
+// 入场券签名密钥，未设置时每次启动随机生成
+#[derive(Clone, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct TicketConfig {
+    pub secret: Option<String>,
+}
+
+// Debug 输出时隐藏密钥
+impl fmt::Debug for TicketConfig {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("TicketConfig")
+            .field("secret", &self.secret.as_ref().map(|_| "<redacted>"))
+            .finish()
+    }
+}
+
+impl Config {
+    // 依次合并默认值、配置文件、环境变量和命令行参数，并在启动前校验
+    pub fn load() -> Result<Self, String> {
+        let cli = Cli::parse();
+        let path = cli.config.clone()
+            .or_else(|| std::env::var_os("CHATROOM_CONFIG").filter(|path| !path.is_empty()).map(PathBuf::from));
+        let mut config = match path {
+            Some(path) => Self::from_file(&path)?,
+            None => Self::default(),
+        };
+        config.apply_env(&|name| std::env::var(name).ok())?;
+        config.apply_cli(cli);
+        config.validate()?;
+        Ok(config)
+    }
+
+    fn from_file(path: &Path) -> Result<Self, String> {
+        let content = std::fs::read_to_string(path)
+            .map_err(|e| format!("读取配置文件 {} 失败: {}", path.display(), e))?;
+        toml::from_str(&content)
+            .map_err(|e| format!("解析配置文件 {} 失败: {}", path.display(), e))
+    }
+
+    // env 按名称读取环境变量，测试时可以传入固定的取值
+    fn apply_env(&mut self, env: &dyn Fn(&str) -> Option<String>) -> Result<(), String> {
+        env_override(env, "CHATROOM_HOST", &mut self.server.host)?;
+        env_override(env, "CHATROOM_PORT", &mut self.server.port)?;
+        if let Some(workers) = env_value(env, "WORKER_THREADS")? {
+            self.server.workers = Some(workers);
+        }
+        env_override(env, "MAX_CONNECTIONS", &mut self.server.max_connections)?;
+        env_override(env, "MAX_CONNECTIONS_PER_IP", &mut self.server.max_connections_per_ip)?;
+        env_override(env, "SHUTDOWN_TIMEOUT", &mut self.server.shutdown_timeout)?;
+        env_override(env, "HEARTBEAT_INTERVAL", &mut self.websocket.heartbeat_interval)?;
+        env_override(env, "CLIENT_TIMEOUT", &mut self.websocket.client_timeout)?;
+        env_override(env, "RECONNECT_AFTER_MS", &mut self.websocket.reconnect_after_ms)?;
+        env_override(env, "HISTORY_SIZE", &mut self.rooms.history_size)?;
+        env_override(env, "ROOM_IDLE_TTL", &mut self.rooms.idle_ttl)?;
+        env_override(env, "ROOM_MAX_MEMBERS", &mut self.rooms.max_members)?;
+        env_override(env, "MAX_MESSAGE_LENGTH", &mut self.limits.max_message_length)?;
+        env_override(env, "MAX_USERNAME_LENGTH", &mut self.limits.max_username_length)?;
+        env_override(env, "CHAT_RATE_LIMIT", &mut self.rate_limit.chat.rate)?;
+        env_override(env, "CHAT_RATE_BURST", &mut self.rate_limit.chat.burst)?;
+        env_override(env, "CHAT_RATE_MAX_VIOLATIONS", &mut self.rate_limit.chat_max_violations)?;
+        env_override(env, "API_RATE_LIMIT", &mut self.rate_limit.api.rate)?;
+        env_override(env, "API_RATE_BURST", &mut self.rate_limit.api.burst)?;
+        env_override(env, "ROOM_CREATE_RATE_LIMIT", &mut self.rate_limit.room_create.rate)?;
+        env_override(env, "ROOM_CREATE_RATE_BURST", &mut self.rate_limit.room_create.burst)?;
+        env_override(env, "RATE_LIMIT_TRUST_PROXY", &mut self.rate_limit.trust_proxy)?;
+        env_override(env, "ROOM_STORE", &mut self.store.backend)?;
+        env_override(env, "ROOM_STORE_PATH", &mut self.store.path)?;
+        if let Some(path) = env_value(env, "TLS_CERT_PATH")? {
+            self.tls.cert_path = Some(path);
+        }
+        if let Some(path) = env_value(env, "TLS_KEY_PATH")? {
+            self.tls.key_path = Some(path);
+        }
+        env_override(env, "ENABLE_METRICS", &mut self.metrics.enabled)?;
+        if let Some(port) = env_value(env, "METRICS_PORT")? {
+            self.metrics.port = Some(port);
+        }
+        env_override(env, "LOG_FORMAT", &mut self.log.format)?;
+        env_override(env, "LOG_MESSAGE_CONTENT", &mut self.log.message_content)?;
+        if let Some(secret) = env_value(env, "CHATROOM_TICKET_SECRET")? {
+            self.ticket.secret = Some(secret);
+        }
+        Ok(())
+    }
+
+    fn apply_cli(&mut self, cli: Cli) {
+        if let Some(host) = cli.host {
+            self.server.host = host;
+        }
+        if let Some(port) = cli.port {
+            self.server.port = port;
+        }
+        if cli.workers.is_some() {
+            self.server.workers = cli.workers;
+        }
+        if let Some(max_connections) = cli.max_connections {
+            self.server.max_connections = max_connections;
+        }
+        if let Some(max_connections_per_ip) = cli.max_connections_per_ip {
+            self.server.max_connections_per_ip = max_connections_per_ip;
+        }
+        if let Some(shutdown_timeout) = cli.shutdown_timeout {
+            self.server.shutdown_timeout = shutdown_timeout;
+        }
+        if let Some(heartbeat_interval) = cli.heartbeat_interval {
+            self.websocket.heartbeat_interval = heartbeat_interval;
+        }
+        if let Some(client_timeout) = cli.client_timeout {
+            self.websocket.client_timeout = client_timeout;
+        }
+        if let Some(backend) = cli.store {
+            self.store.backend = backend;
+        }
+        if let Some(path) = cli.store_path {
+            self.store.path = path;
+        }
+        if cli.tls_cert.is_some() {
+            self.tls.cert_path = cli.tls_cert;
+        }
+        if cli.tls_key.is_some() {
+            self.tls.key_path = cli.tls_key;
+        }
+        if cli.enable_metrics {
+            self.metrics.enabled = true;
+        }
+        if cli.metrics_port.is_some() {
+            self.metrics.port = cli.metrics_port;
+        }
+        if let Some(format) = cli.log_format {
+            self.log.format = format;
+        }
+        if cli.ticket_secret.is_some() {
+            self.ticket.secret = cli.ticket_secret;
+        }
+    }
+
+    fn validate(&self) -> Result<(), String> {
+        if self.server.host.trim().is_empty() {
+            return Err("监听地址不能为空".to_string());
+        }
+        if self.server.workers == Some(0) {
+            return Err("工作线程数必须大于 0".to_string());
+        }
+        if self.server.max_connections == 0 || self.server.max_connections_per_ip == 0 {
+            return Err("最大连接数必须大于 0".to_string());
+        }
+        if self.server.shutdown_timeout == 0 {
+            return Err("退出等待时间必须大于 0".to_string());
+        }
+        if self.websocket.heartbeat_interval == 0 {
+            return Err("心跳间隔必须大于 0".to_string());
+        }
+        if self.websocket.client_timeout <= self.websocket.heartbeat_interval {
+            return Err("客户端超时时间必须大于心跳间隔".to_string());
+        }
+        if self.rooms.history_size == 0 {
+            return Err("消息历史条数必须大于 0".to_string());
+        }
+        if self.rooms.max_members == 0 {
+            return Err("房间人数上限必须大于 0".to_string());
+        }
+        if self.rooms.idle_ttl == 0 {
+            return Err("空闲房间保留时间必须大于 0".to_string());
+        }
+        if self.rooms.idle_ttl().is_none() {
+            return Err("空闲房间保留时间过长".to_string());
+        }
+        if self.limits.max_message_length == 0 || self.limits.max_username_length == 0 {
+            return Err("消息内容和昵称的长度上限必须大于 0".to_string());
+        }
+        for (name, limit) in [("chat", self.rate_limit.chat), ("api", self.rate_limit.api), ("room_create", self.rate_limit.room_create)] {
+            let valid = limit.rate.is_finite() && limit.rate >= 0.0 && limit.burst.is_finite() && limit.burst >= 1.0;
+            if !valid {
+                return Err(format!("限流参数 {} 无效：速率不能为负数，突发上限不能小于 1", name));
+            }
+        }
+        if self.store.backend == StoreBackend::Sqlite && self.store.path.trim().is_empty() {
+            return Err("SQLite 数据库路径不能为空".to_string());
+        }
+        if self.tls.cert_path.is_some() != self.tls.key_path.is_some() {
+            return Err("TLS 证书和私钥路径必须同时设置".to_string());
+        }
+        if self.metrics.enabled && self.metrics.port == Some(self.server.port) {
+            return Err("监控端口不能与聊天服务端口相同，共用端口时请不要设置 METRICS_PORT".to_string());
+        }
+        if self.ticket.secret.as_ref().is_some_and(|secret| secret.trim().len() < MIN_TICKET_SECRET_LEN) {
+            return Err(format!("入场券签名密钥至少需要 {} 个字节", MIN_TICKET_SECRET_LEN));
+        }
+        Ok(())
+    }
+}
+
+// 未设置或为空时返回 None，格式错误时返回错误而不是静默忽略
+fn env_value<T>(env: &dyn Fn(&str) -> Option<String>, name: &str) -> Result<Option<T>, String>
+where
+    T: FromStr,
+    T::Err: Display,
+{
+    match env(name) {
+        Some(value) if !value.trim().is_empty() => value.trim()
+            .parse()
+            .map(Some)
+            .map_err(|e| format!("环境变量 {}={} 无效: {}", name, value, e)),
+        _ => Ok(None),
+    }
+}
+
+fn env_override<T>(env: &dyn Fn(&str) -> Option<String>, name: &str, target: &mut T) -> Result<(), String>
+where
+    T: FromStr,
+    T::Err: Display,
+{
+    if let Some(value) = env_value(env, name)? {
+        *target = value;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+
+    fn load(toml: &str, env: &[(&str, &str)]) -> Result<Config, String> {
+        let env: HashMap<String, String> = env.iter().map(|(name, value)| (name.to_string(), value.to_string())).collect();
+        let mut config: Config = toml::from_str(toml).map_err(|e| e.to_string())?;
+        config.apply_env(&|name| env.get(name).cloned())?;
+        config.validate()?;
+        Ok(config)
+    }
+
+    #[test]
+    fn env_overrides_toml_overrides_default() {
+        let config = load(
+            "[server]\nport = 9000\nmax_connections = 50\n\n[rooms]\nidle_ttl = 60\n",
+            &[("CHATROOM_PORT", "9100"), ("ROOM_IDLE_TTL", "")],
+        ).unwrap();
+
+        // 环境变量优先于配置文件
+        assert_eq!(config.server.port, 9100);
+        // 只在配置文件中设置，空的环境变量视为未设置
+        assert_eq!(config.server.max_connections, 50);
+        assert_eq!(config.rooms.idle_ttl, 60);
+        // 都未设置时使用默认值
+        assert_eq!(config.server.max_connections_per_ip, ServerConfig::default().max_connections_per_ip);
+        assert_eq!(config.server.host, "0.0.0.0");
+    }
+
+    #[test]
+    fn rejects_invalid_env_value() {
+        let error = load("", &[("CHATROOM_PORT", "http")]).unwrap_err();
+        assert!(error.contains("CHATROOM_PORT"));
+    }
+
+    #[test]
+    fn rejects_unknown_toml_field() {
+        assert!(load("[server]\nprot = 9000\n", &[]).is_err());
+    }
+
+    #[test]
+    fn rejects_zero_idle_ttl() {
+        assert!(load("[rooms]\nidle_ttl = 0\n", &[]).is_err());
+        assert!(load("", &[("ROOM_IDLE_TTL", "0")]).is_err());
+    }
+
+    #[test]
+    fn ticket_secret_from_env_must_be_long_enough() {
+        assert!(load("[ticket]\nsecret = \"0123456789abcdef0123456789abcdef\"\n", &[("CHATROOM_TICKET_SECRET", "short")]).is_err());
+
+        let secret = "x".repeat(MIN_TICKET_SECRET_LEN);
+        let config = load("", &[("CHATROOM_TICKET_SECRET", &secret)]).unwrap();
+        assert_eq!(config.ticket.secret.as_deref(), Some(secret.as_str()));
+        assert!(!format!("{:?}", config.ticket).contains(&secret));
+    }
+}
@@ -0,0 +1,153 @@
+use actix_web::http::StatusCode;
+use actix_web::{HttpResponse, ResponseError};
+use std::fmt;
+use crate::handlers::ApiResponse;
+use crate::validation::ValidationError;
+
+// HTTP 接口和 WebSocket 共用的错误类型，code() 返回的错误代码保持稳定，客户端据此区分处理
+#[derive(Debug, Clone, PartialEq)]
+pub enum ChatError {
+    RoomNotFound,
+    RoomNameEmpty,
+    RoomFull,
+    InvalidMaxMembers(usize),
+    ServerFull,
+    TooManyConnections,
+    // 服务器正在排空连接准备退出
+    ShuttingDown,
+    PasswordEmpty,
+    PasswordRequired,
+    BadPassword,
+    Forbidden,
+    UsernameTaken,
+    UserNotFound,
+    UserOffline,
+    DirectToSelf,
+    AlreadyJoined,
+    NotJoined,
+    InvalidTicket,
+    TicketExpired,
+    SessionExpired,
+    SessionReplaced,
+    HistoryDisabled,
+    MessageNotFound,
+    NotMessageSender,
+    ReplyNotFound,
+    InvalidEmoji,
+    TooManyReactions,
+    RateLimited,
+    RateLimitExceeded,
+    InvalidRequest(String),
+    Validation(ValidationError),
+    // 存储、密码哈希等服务器端故障，详情只写入日志
+    Internal(String),
+}
+
+impl ChatError {
+    pub fn code(&self) -> &'static str {
+        match self {
+            Self::RoomNotFound => "ROOM_NOT_FOUND",
+            Self::RoomNameEmpty => "ROOM_NAME_EMPTY",
+            Self::RoomFull => "ROOM_FULL",
+            Self::InvalidMaxMembers(_) => "INVALID_MAX_MEMBERS",
+            Self::ServerFull => "SERVER_FULL",
+            Self::TooManyConnections => "TOO_MANY_CONNECTIONS",
+            Self::ShuttingDown => "SERVER_SHUTTING_DOWN",
+            Self::PasswordEmpty => "PASSWORD_EMPTY",
+            Self::PasswordRequired => "PASSWORD_REQUIRED",
+            Self::BadPassword => "BAD_PASSWORD",
+            Self::Forbidden => "FORBIDDEN",
+            Self::UsernameTaken => "USERNAME_TAKEN",
+            Self::UserNotFound => "USER_NOT_FOUND",
+            Self::UserOffline => "USER_OFFLINE",
+            Self::DirectToSelf => "DIRECT_TO_SELF",
+            Self::AlreadyJoined => "ALREADY_JOINED",
+            Self::NotJoined => "NOT_JOINED",
+            Self::InvalidTicket => "INVALID_TICKET",
+            Self::TicketExpired => "TICKET_EXPIRED",
+            Self::SessionExpired => "SESSION_EXPIRED",
+            Self::SessionReplaced => "SESSION_REPLACED",
+            Self::HistoryDisabled => "HISTORY_DISABLED",
+            Self::MessageNotFound => "MESSAGE_NOT_FOUND",
+            Self::NotMessageSender => "NOT_MESSAGE_SENDER",
+            Self::ReplyNotFound => "REPLY_NOT_FOUND",
+            Self::InvalidEmoji => "INVALID_EMOJI",
+            Self::TooManyReactions => "TOO_MANY_REACTIONS",
+            Self::RateLimited => "RATE_LIMITED",
+            Self::RateLimitExceeded => "RATE_LIMIT_EXCEEDED",
+            Self::InvalidRequest(_) => "INVALID_REQUEST",
+            Self::Validation(e) => e.code(),
+            Self::Internal(_) => "INTERNAL_ERROR",
+        }
+    }
+}
+
+impl fmt::Display for ChatError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::RoomNotFound => write!(f, "聊天室不存在"),
+            Self::RoomNameEmpty => write!(f, "聊天室名称不能为空"),
+            Self::RoomFull => write!(f, "聊天室人数已满"),
+            Self::InvalidMaxMembers(max) => write!(f, "房间人数上限必须在 1 到 {} 之间", max),
+            Self::ServerFull => write!(f, "服务器连接数已满，请稍后再试"),
+            Self::TooManyConnections => write!(f, "当前网络打开的连接过多，请关闭其他页面后重试"),
+            Self::ShuttingDown => write!(f, "服务器正在重启，请稍后重新连接"),
+            Self::PasswordEmpty => write!(f, "密码不能为空"),
+            Self::PasswordRequired => write!(f, "该聊天室需要密码"),
+            Self::BadPassword => write!(f, "密码错误"),
+            Self::Forbidden => write!(f, "需要房主权限"),
+            Self::UsernameTaken => write!(f, "昵称已被房间内其他用户使用"),
+            Self::UserNotFound => write!(f, "用户不在聊天室中"),
+            Self::UserOffline => write!(f, "对方已离开聊天室或暂时离线"),
+            Self::DirectToSelf => write!(f, "不能给自己发送私信"),
+            Self::AlreadyJoined => write!(f, "已经加入聊天室"),
+            Self::NotJoined => write!(f, "尚未加入聊天室"),
+            Self::InvalidTicket => write!(f, "入场券无效"),
+            Self::TicketExpired => write!(f, "入场券已过期"),
+            Self::SessionExpired => write!(f, "会话已过期，请重新加入聊天室"),
+            Self::SessionReplaced => write!(f, "会话已在其他连接上恢复"),
+            Self::HistoryDisabled => write!(f, "该聊天室未开启消息历史"),
+            Self::MessageNotFound => write!(f, "消息不存在或已无法修改"),
+            Self::NotMessageSender => write!(f, "只能修改或删除自己发送的消息"),
+            Self::ReplyNotFound => write!(f, "回复的消息不存在"),
+            Self::InvalidEmoji => write!(f, "表情无效"),
+            Self::TooManyReactions => write!(f, "该消息的表情回应已达上限"),
+            Self::RateLimited => write!(f, "请求过于频繁，请稍后再试"),
+            Self::RateLimitExceeded => write!(f, "发送过于频繁，连接已断开"),
+            Self::InvalidRequest(detail) => write!(f, "请求格式错误: {}", detail),
+            Self::Validation(e) => write!(f, "{}", e),
+            Self::Internal(_) => write!(f, "服务器内部错误"),
+        }
+    }
+}
+
+impl From<ValidationError> for ChatError {
+    fn from(error: ValidationError) -> Self {
+        Self::Validation(error)
+    }
+}
+
+impl ResponseError for ChatError {
+    fn status_code(&self) -> StatusCode {
+        match self {
+            Self::RoomNotFound | Self::UserNotFound | Self::UserOffline
+            | Self::MessageNotFound | Self::ReplyNotFound | Self::HistoryDisabled => StatusCode::NOT_FOUND,
+            Self::PasswordRequired | Self::BadPassword | Self::InvalidTicket
+            | Self::TicketExpired | Self::SessionExpired => StatusCode::UNAUTHORIZED,
+            Self::Forbidden | Self::NotMessageSender => StatusCode::FORBIDDEN,
+            Self::UsernameTaken | Self::AlreadyJoined | Self::NotJoined | Self::SessionReplaced | Self::RoomFull => StatusCode::CONFLICT,
+            Self::ServerFull | Self::TooManyConnections | Self::ShuttingDown => StatusCode::SERVICE_UNAVAILABLE,
+            Self::RateLimited | Self::RateLimitExceeded => StatusCode::TOO_MANY_REQUESTS,
+            Self::Internal(_) => StatusCode::INTERNAL_SERVER_ERROR,
+            Self::RoomNameEmpty | Self::InvalidMaxMembers(_) | Self::PasswordEmpty | Self::DirectToSelf
+            | Self::InvalidEmoji | Self::TooManyReactions | Self::InvalidRequest(_) | Self::Validation(_) => StatusCode::BAD_REQUEST,
+        }
+    }
+
+    fn error_response(&self) -> HttpResponse {
+        if let Self::Internal(detail) = self {
+            log::error!("处理请求失败: {}", detail);
+        }
+        HttpResponse::build(self.status_code()).json(ApiResponse::<()>::error(self))
+    }
+}
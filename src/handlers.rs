@@ -1,33 +1,76 @@
-use actix_web::{web, HttpRequest, HttpResponse, Result, get, post};
+use actix_web::{web, http::header, HttpMessage, HttpRequest, HttpResponse, Result, get, post, put, delete};
 use actix_web_actors::ws;
 use serde::{Deserialize, Serialize};
 use std::sync::Arc;
 use tokio::sync::RwLock;
-use crate::chatroom::ChatRoomManager;
-use crate::websocket::{WebSocketSession, WebSocketManager};
+use crate::chatroom::{ChatRoom, ChatRoomManager, HashedPassword};
+use crate::websocket::{ServerMessage, WebSocketSession, WebSocketManager};
+use crate::ticket::TicketSigner;
+use crate::ratelimit::RateLimiter;
+use crate::validation::ContentLimits;
+use crate::error::ChatError;
+use crate::config::{Config, LimitsConfig};
+use crate::metrics::Metrics;
+use crate::shutdown::Shutdown;
+use crate::logging::RequestId;
 
 #[derive(Deserialize)]
 pub struct CreateRoomRequest {
     name: String,
     password: Option<String>,
+    // 是否在服务器端保存消息历史
+    #[serde(default)]
+    history: bool,
+    // 房间最大人数，省略时使用服务器配置的默认值
+    max_members: Option<usize>,
 }
 
 #[derive(Deserialize)]
 pub struct JoinRoomRequest {
     room_id: String,
+    username: String,
     password: Option<String>,
 }
 
 #[derive(Deserialize)]
 pub struct UpdatePasswordRequest {
     room_id: String,
-    new_password: String,
+    // 为空表示移除密码
+    new_password: Option<String>,
+    current_password: Option<String>,
+    // 已改为 Authorization: Bearer 头，保留以兼容旧客户端
+    owner_token: Option<String>,
+}
+
+#[derive(Deserialize)]
+pub struct RenameRoomRequest {
+    name: String,
+}
+
+#[derive(Deserialize)]
+pub struct KickUserRequest {
+    user_id: String,
+}
+
+#[derive(Deserialize)]
+pub struct TransferOwnershipRequest {
+    user_id: String,
+}
+
+#[derive(Deserialize)]
+pub struct MessagesQuery {
+    // 消息序号，返回比它更早的消息
+    before: Option<u64>,
+    limit: Option<usize>,
 }
 
 #[derive(Serialize)]
 pub struct ApiResponse<T> {
     success: bool,
     data: Option<T>,
+    // 失败时的错误代码，见 ChatError::code
+    #[serde(skip_serializing_if = "Option::is_none")]
+    code: Option<&'static str>,
     message: Option<String>,
 }
 
@@ -36,19 +79,88 @@ impl<T> ApiResponse<T> {
         Self {
             success: true,
             data: Some(data),
+            code: None,
             message: None,
         }
     }
 
-    pub fn error(message: String) -> Self {
+    pub fn error(error: &ChatError) -> Self {
         Self {
             success: false,
             data: None,
-            message: Some(message),
+            code: Some(error.code()),
+            message: Some(error.to_string()),
         }
     }
 }
 
+// 从 Authorization: Bearer <token> 头中取出房主令牌
+fn owner_token(req: &HttpRequest) -> Option<&str> {
+    req.headers()
+        .get(header::AUTHORIZATION)?
+        .to_str()
+        .ok()?
+        .strip_prefix("Bearer ")
+}
+
+// 校验请求方是否为房主
+fn authorize_owner(manager: &ChatRoomManager, room_id: &str, req: &HttpRequest) -> std::result::Result<(), ChatError> {
+    match manager.get_room(room_id) {
+        Some(room) if owner_token(req).is_some_and(|token| room.is_owner(token)) => Ok(()),
+        Some(_) => Err(ChatError::Forbidden),
+        None => Err(ChatError::RoomNotFound),
+    }
+}
+
+// 校验房间密码；只在读锁内复制密码哈希，Argon2 校验在锁外进行，不会阻塞其他房间的消息
+async fn check_room_password(
+    chat_manager: &RwLock<ChatRoomManager>,
+    room_id: &str,
+    password: Option<&str>,
+) -> std::result::Result<(), ChatError> {
+    let Some(room_password) = chat_manager.read().await.room_password(room_id)? else {
+        return Ok(());
+    };
+    let password = password.ok_or(ChatError::PasswordRequired)?;
+    if !room_password.check(password.to_string()).await? {
+        return Err(ChatError::BadPassword);
+    }
+    Ok(())
+}
+
+// 非空时在锁外哈希
+async fn hash_password(password: Option<String>) -> std::result::Result<Option<HashedPassword>, ChatError> {
+    match password {
+        Some(password) => Ok(Some(HashedPassword::hash(password).await?)),
+        None => Ok(None),
+    }
+}
+
+// 校验密码、人数和昵称，返回房间和规范化后的昵称
+async fn admit(
+    chat_manager: &RwLock<ChatRoomManager>,
+    ws_manager: &RwLock<WebSocketManager>,
+    limits: &LimitsConfig,
+    req: &JoinRoomRequest,
+) -> std::result::Result<(ChatRoom, String), ChatError> {
+    // 规范化后的昵称写入入场券，WebSocket 加入时直接使用
+    let username = ContentLimits::new(limits).normalize_username(&req.username)?;
+    check_room_password(chat_manager, &req.room_id, req.password.as_deref()).await?;
+
+    let manager = chat_manager.read().await;
+    let room = manager.get_room(&req.room_id).ok_or(ChatError::RoomNotFound)?;
+
+    // 提前检查人数和昵称，WebSocket 加入时仍会再次校验
+    let ws_manager = ws_manager.read().await;
+    if ws_manager.user_count(&room.id) >= room.max_members {
+        return Err(ChatError::RoomFull);
+    }
+    if ws_manager.is_username_taken(&room.id, &username) {
+        return Err(ChatError::UsernameTaken);
+    }
+    Ok((room.clone(), username))
+}
+
 #[get("/")]
 pub async fn index() -> Result<HttpResponse> {
     let html = include_str!("../static/index.html");
@@ -62,91 +174,296 @@ pub async fn create_room(
     chat_manager: web::Data<Arc<RwLock<ChatRoomManager>>>,
     req: web::Json<CreateRoomRequest>,
 ) -> Result<HttpResponse> {
+    let password = hash_password(req.password.clone().filter(|password| !password.is_empty())).await?;
     let mut manager = chat_manager.write().await;
-    
-    match manager.create_room(req.name.clone(), req.password.clone()) {
-        Ok(room_id) => {
-            #[derive(Serialize)]
-            struct CreateRoomResponse {
-                room_id: String,
-            }
-            
-            Ok(HttpResponse::Ok().json(ApiResponse::success(CreateRoomResponse { room_id })))
-        }
-        Err(e) => Ok(HttpResponse::BadRequest().json(ApiResponse::<()>::error(e))),
+    let (room_id, owner_token) = manager.create_room(req.name.clone(), password, req.history, req.max_members)?;
+
+    #[derive(Serialize)]
+    struct CreateRoomResponse {
+        room_id: String,
+        owner_token: String,
     }
+
+    Ok(HttpResponse::Ok().json(ApiResponse::success(CreateRoomResponse { room_id, owner_token })))
 }
 
 #[get("/api/rooms")]
 pub async fn list_rooms(
     chat_manager: web::Data<Arc<RwLock<ChatRoomManager>>>,
+    ws_manager: web::Data<Arc<RwLock<WebSocketManager>>>,
 ) -> Result<HttpResponse> {
     let manager = chat_manager.read().await;
-    let rooms = manager.list_rooms();
+    let ws_manager = ws_manager.read().await;
+    let rooms = manager.list_rooms(&ws_manager);
     Ok(HttpResponse::Ok().json(ApiResponse::success(rooms)))
 }
 
 #[post("/api/rooms/join")]
 pub async fn join_room(
     chat_manager: web::Data<Arc<RwLock<ChatRoomManager>>>,
+    ws_manager: web::Data<Arc<RwLock<WebSocketManager>>>,
+    ticket_signer: web::Data<Arc<TicketSigner>>,
+    config: web::Data<Arc<Config>>,
+    metrics: web::Data<Arc<Metrics>>,
     req: web::Json<JoinRoomRequest>,
 ) -> Result<HttpResponse> {
-    let manager = chat_manager.read().await;
-    
-    match manager.get_room(&req.room_id) {
-        Some(room) => {
-            if let Some(room_password) = &room.password {
-                match &req.password {
-                    Some(provided_password) => {
-                        if room_password != provided_password {
-                            return Ok(HttpResponse::Unauthorized().json(ApiResponse::<()>::error("密码错误".to_string())));
-                        }
-                    }
-                    None => {
-                        return Ok(HttpResponse::BadRequest().json(ApiResponse::<()>::error("该聊天室需要密码".to_string())));
-                    }
-                }
-            }
-            
-            #[derive(Serialize)]
-            struct JoinRoomResponse {
-                room_id: String,
-                room_name: String,
-            }
-            
-            Ok(HttpResponse::Ok().json(ApiResponse::success(JoinRoomResponse {
-                room_id: room.id.clone(),
-                room_name: room.name.clone(),
-            })))
-        }
-        None => Ok(HttpResponse::NotFound().json(ApiResponse::<()>::error("聊天室不存在".to_string()))),
+    let (room, username) = admit(&chat_manager, &ws_manager, &config.limits, &req)
+        .await
+        .inspect_err(|e| metrics.join_failed("http", e))?;
+
+    #[derive(Serialize)]
+    struct JoinRoomResponse {
+        room_id: String,
+        room_name: String,
+        ticket: String,
     }
+
+    Ok(HttpResponse::Ok().json(ApiResponse::success(JoinRoomResponse {
+        room_id: room.id.clone(),
+        room_name: room.name.clone(),
+        ticket: ticket_signer.issue(&room.id, &username),
+    })))
 }
 
 #[post("/api/rooms/password")]
 pub async fn update_password(
+    http_req: HttpRequest,
     chat_manager: web::Data<Arc<RwLock<ChatRoomManager>>>,
     req: web::Json<UpdatePasswordRequest>,
 ) -> Result<HttpResponse> {
+    // 与其他房主接口一样从 Authorization 头取令牌，请求体中的 owner_token 仅为兼容旧客户端保留
+    let token = owner_token(&http_req).or(req.owner_token.as_deref());
+    let is_owner = |room: &ChatRoom| token.is_some_and(|token| room.is_owner(token));
+    let (room_password, owner) = {
+        let manager = chat_manager.read().await;
+        let room = manager.get_room(&req.room_id).ok_or(ChatError::RoomNotFound)?;
+        (room.password.clone(), is_owner(room))
+    };
+
+    // 房主令牌或当前密码二者之一即可授权；无密码的房间只有房主能设置密码
+    if !owner {
+        let authorized = match (room_password.clone(), req.current_password.clone()) {
+            (Some(room_password), Some(current_password)) => room_password.check(current_password).await?,
+            _ => false,
+        };
+        if !authorized {
+            return Err(ChatError::Forbidden.into());
+        }
+    }
+
+    let new_password = req.new_password.clone().filter(|password| !password.is_empty());
+    if new_password.as_ref().is_some_and(|password| password.trim().is_empty()) {
+        return Err(ChatError::PasswordEmpty.into());
+    }
+    let removed = new_password.is_none();
+    let new_password = hash_password(new_password).await?;
+
     let mut manager = chat_manager.write().await;
-    
-    match manager.update_room_password(&req.room_id, req.new_password.clone()) {
-        Ok(()) => Ok(HttpResponse::Ok().json(ApiResponse::success("密码更新成功"))),
-        Err(e) => Ok(HttpResponse::BadRequest().json(ApiResponse::<()>::error(e))),
+    // 哈希和校验期间房主令牌或密码可能已被修改，此时旧的凭据随即失效
+    let room = manager.get_room(&req.room_id).ok_or(ChatError::RoomNotFound)?;
+    let still_authorized = if owner { is_owner(room) } else { room.password == room_password };
+    if !still_authorized {
+        return Err(ChatError::Forbidden.into());
+    }
+    manager.update_room_password(&req.room_id, new_password)?;
+
+    if removed {
+        Ok(HttpResponse::Ok().json(ApiResponse::success("密码已移除")))
+    } else {
+        Ok(HttpResponse::Ok().json(ApiResponse::success("密码更新成功")))
     }
 }
 
+#[put("/api/rooms/{room_id}/name")]
+pub async fn rename_room(
+    http_req: HttpRequest,
+    path: web::Path<String>,
+    chat_manager: web::Data<Arc<RwLock<ChatRoomManager>>>,
+    ws_manager: web::Data<Arc<RwLock<WebSocketManager>>>,
+    req: web::Json<RenameRoomRequest>,
+) -> Result<HttpResponse> {
+    let room_id = path.into_inner();
+    let mut manager = chat_manager.write().await;
+
+    authorize_owner(&manager, &room_id, &http_req)?;
+
+    manager.rename_room(&room_id, req.name.clone())?;
+    ws_manager.read().await.broadcast_to_room(&room_id, ServerMessage::RoomRenamed {
+        name: req.name.clone(),
+    }, None);
+    Ok(HttpResponse::Ok().json(ApiResponse::success("聊天室已重命名")))
+}
+
+#[delete("/api/rooms/{room_id}")]
+pub async fn delete_room(
+    http_req: HttpRequest,
+    path: web::Path<String>,
+    chat_manager: web::Data<Arc<RwLock<ChatRoomManager>>>,
+    ws_manager: web::Data<Arc<RwLock<WebSocketManager>>>,
+) -> Result<HttpResponse> {
+    let room_id = path.into_inner();
+    let mut manager = chat_manager.write().await;
+
+    authorize_owner(&manager, &room_id, &http_req)?;
+
+    manager.delete_room(&room_id);
+    ws_manager.write().await.close_room(&room_id, ServerMessage::RoomClosed {
+        room_id: room_id.clone(),
+    });
+    Ok(HttpResponse::Ok().json(ApiResponse::success("聊天室已删除")))
+}
+
+#[post("/api/rooms/{room_id}/kick")]
+pub async fn kick_user(
+    http_req: HttpRequest,
+    path: web::Path<String>,
+    chat_manager: web::Data<Arc<RwLock<ChatRoomManager>>>,
+    ws_manager: web::Data<Arc<RwLock<WebSocketManager>>>,
+    req: web::Json<KickUserRequest>,
+) -> Result<HttpResponse> {
+    let room_id = path.into_inner();
+    let manager = chat_manager.read().await;
+
+    authorize_owner(&manager, &room_id, &http_req)?;
+
+    let kicked = ws_manager.write().await.disconnect_user(&room_id, &req.user_id, ServerMessage::Kicked {
+        room_id: room_id.clone(),
+    });
+    if !kicked {
+        return Err(ChatError::UserNotFound.into());
+    }
+    Ok(HttpResponse::Ok().json(ApiResponse::success("用户已被移出聊天室")))
+}
+
+// 将房主身份转交给房间内的在线用户，新令牌通过 WebSocket 发给对方，旧令牌立即失效
+#[post("/api/rooms/{room_id}/transfer")]
+pub async fn transfer_ownership(
+    http_req: HttpRequest,
+    path: web::Path<String>,
+    chat_manager: web::Data<Arc<RwLock<ChatRoomManager>>>,
+    ws_manager: web::Data<Arc<RwLock<WebSocketManager>>>,
+    req: web::Json<TransferOwnershipRequest>,
+) -> Result<HttpResponse> {
+    let room_id = path.into_inner();
+    let mut manager = chat_manager.write().await;
+
+    authorize_owner(&manager, &room_id, &http_req)?;
+
+    let ws_manager = ws_manager.read().await;
+    if !ws_manager.is_member(&room_id, &req.user_id) {
+        return Err(ChatError::UserNotFound.into());
+    }
+
+    let owner_token = manager.rotate_owner_token(&room_id)?;
+
+    ws_manager.send_to_user(&room_id, &req.user_id, ServerMessage::OwnershipGranted {
+        room_id: room_id.clone(),
+        owner_token,
+    });
+    Ok(HttpResponse::Ok().json(ApiResponse::success("房主已转让")))
+}
+
+// 房间当前成员，有密码的房间需要在 X-Room-Password 头中提供密码
+#[get("/api/rooms/{room_id}/members")]
+pub async fn list_members(
+    http_req: HttpRequest,
+    path: web::Path<String>,
+    chat_manager: web::Data<Arc<RwLock<ChatRoomManager>>>,
+    ws_manager: web::Data<Arc<RwLock<WebSocketManager>>>,
+) -> Result<HttpResponse> {
+    let room_id = path.into_inner();
+
+    let password = http_req.headers()
+        .get("X-Room-Password")
+        .and_then(|value| value.to_str().ok());
+    check_room_password(&chat_manager, &room_id, password).await?;
+
+    let members = ws_manager.read().await.members(&room_id);
+    Ok(HttpResponse::Ok().json(ApiResponse::success(members)))
+}
+
+// 分页获取服务器端消息历史，有密码的房间需要在 X-Room-Password 头中提供密码
+#[get("/api/rooms/{room_id}/messages")]
+pub async fn list_messages(
+    http_req: HttpRequest,
+    path: web::Path<String>,
+    chat_manager: web::Data<Arc<RwLock<ChatRoomManager>>>,
+    query: web::Query<MessagesQuery>,
+) -> Result<HttpResponse> {
+    let room_id = path.into_inner();
+
+    let password = http_req.headers()
+        .get("X-Room-Password")
+        .and_then(|value| value.to_str().ok());
+    check_room_password(&chat_manager, &room_id, password).await?;
+
+    let limit = query.limit.unwrap_or(50).clamp(1, 100);
+    let messages = chat_manager.read().await.messages_before(&room_id, query.before, limit)?;
+    Ok(HttpResponse::Ok().json(ApiResponse::success(messages)))
+}
+
 #[get("/ws")]
+#[allow(clippy::too_many_arguments)]
 pub async fn websocket_handler(
     req: HttpRequest,
     stream: web::Payload,
-    _chat_manager: web::Data<Arc<RwLock<ChatRoomManager>>>,
+    chat_manager: web::Data<Arc<RwLock<ChatRoomManager>>>,
     ws_manager: web::Data<Arc<RwLock<WebSocketManager>>>,
+    ticket_signer: web::Data<Arc<TicketSigner>>,
+    rate_limiter: web::Data<Arc<RateLimiter>>,
+    config: web::Data<Arc<Config>>,
+    metrics: web::Data<Arc<Metrics>>,
+    shutdown: web::Data<Arc<Shutdown>>,
 ) -> Result<HttpResponse> {
-    let session = WebSocketSession::new(ws_manager.get_ref().clone());
+    // 排空期间或连接数已满时在升级前返回 503
+    let limiter = if shutdown.is_draining() {
+        Err(ChatError::ShuttingDown)
+    } else {
+        rate_limiter.connection_limiter(&req)
+    }.inspect_err(|e| metrics.join_failed("websocket", e))?;
+    let request_id = req.extensions().get::<RequestId>().map(|id| id.0.clone());
+    let session = WebSocketSession::new(
+        chat_manager.get_ref().clone(),
+        ws_manager.get_ref().clone(),
+        ticket_signer.get_ref().clone(),
+        limiter,
+        &config,
+        metrics.get_ref().clone(),
+        shutdown.subscribe(),
+        request_id,
+    );
     ws::start(session, &req, stream)
 }
 
+// 存活检查，进程能处理请求即返回 200
+#[get("/healthz")]
+pub async fn healthz() -> Result<HttpResponse> {
+    Ok(HttpResponse::Ok().json(ApiResponse::success("ok")))
+}
+
+// 就绪检查，收到退出信号开始排空后返回 503，负载均衡据此摘除实例
+#[get("/readyz")]
+pub async fn readyz(shutdown: web::Data<Arc<Shutdown>>) -> Result<HttpResponse> {
+    if shutdown.is_draining() {
+        return Err(ChatError::ShuttingDown.into());
+    }
+    Ok(HttpResponse::Ok().json(ApiResponse::success("ready")))
+}
+
+// Prometheus 抓取接口，房间数在抓取时读取
+#[get("/metrics")]
+pub async fn export_metrics(
+    chat_manager: web::Data<Arc<RwLock<ChatRoomManager>>>,
+    ws_manager: web::Data<Arc<RwLock<WebSocketManager>>>,
+    metrics: web::Data<Arc<Metrics>>,
+) -> Result<HttpResponse> {
+    metrics.rooms.set(chat_manager.read().await.room_count() as i64);
+    metrics.active_rooms.set(ws_manager.read().await.rooms.len() as i64);
+    Ok(HttpResponse::Ok()
+        .content_type(prometheus::TEXT_FORMAT)
+        .body(metrics.render()?))
+}
+
 pub async fn static_files(path: web::Path<String>) -> Result<HttpResponse> {
     let filename = path.into_inner();
     
@@ -165,4 +482,98 @@ pub async fn static_files(path: web::Path<String>) -> Result<HttpResponse> {
         }
         _ => Ok(HttpResponse::NotFound().body("File not found")),
     }
-}
\ No newline at end of file
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actix_web::{dev::{Service, ServiceResponse}, http::StatusCode, test, App};
+    use serde_json::{json, Value};
+    use crate::config::RoomsConfig;
+    use crate::store::MemoryRoomStore;
+
+    // 带密码 old 的房间，返回房间ID和房主令牌
+    fn password_room() -> (Arc<RwLock<ChatRoomManager>>, String, String) {
+        let mut manager = ChatRoomManager::with_store(Box::new(MemoryRoomStore), &RoomsConfig::default()).unwrap();
+        let password = HashedPassword::new("old").unwrap();
+        let (room_id, token) = manager.create_room("room".to_string(), Some(password), false, None).unwrap();
+        (Arc::new(RwLock::new(manager)), room_id, token)
+    }
+
+    fn request(bearer: Option<&str>, body: Value) -> test::TestRequest {
+        let req = test::TestRequest::post().uri("/api/rooms/password").set_json(body);
+        match bearer {
+            Some(token) => req.insert_header((header::AUTHORIZATION, format!("Bearer {}", token))),
+            None => req,
+        }
+    }
+
+    async fn update<R>(
+        app: &impl Service<R, Response = ServiceResponse, Error = actix_web::Error>,
+        req: R,
+    ) -> (StatusCode, Value) {
+        let resp = test::call_service(app, req).await;
+        (resp.status(), test::read_body_json(resp).await)
+    }
+
+    async fn room_password(chat_manager: &RwLock<ChatRoomManager>, room_id: &str) -> Option<HashedPassword> {
+        chat_manager.read().await.room_password(room_id).unwrap()
+    }
+
+    #[actix_web::test]
+    async fn update_password_requires_owner_or_current_password() {
+        let (chat_manager, room_id, token) = password_room();
+        let app = test::init_service(
+            App::new().app_data(web::Data::new(chat_manager.clone())).service(update_password),
+        ).await;
+
+        let rejected = [
+            (None, json!({ "room_id": room_id, "new_password": "new" })),
+            (None, json!({ "room_id": room_id, "new_password": "new", "current_password": "wrong" })),
+            (Some("wrong"), json!({ "room_id": room_id, "new_password": "new" })),
+            (None, json!({ "room_id": room_id, "new_password": "new", "owner_token": "wrong" })),
+        ];
+        for (bearer, body) in rejected {
+            let (status, body) = update(&app, request(bearer, body).to_request()).await;
+            assert_eq!(status, StatusCode::FORBIDDEN);
+            assert_eq!(body["code"], "FORBIDDEN");
+        }
+        assert!(room_password(&chat_manager, &room_id).await.unwrap().verify("old"));
+
+        let (status, body) = update(&app, request(None, json!({ "room_id": "missing", "new_password": "new", "current_password": "old" })).to_request()).await;
+        assert_eq!((status, body["code"].as_str()), (StatusCode::NOT_FOUND, Some("ROOM_NOT_FOUND")));
+
+        // 当前密码授权后旧密码随即失效
+        let (status, body) = update(&app, request(None, json!({ "room_id": room_id, "new_password": "new", "current_password": "old" })).to_request()).await;
+        assert_eq!((status, body["data"].as_str()), (StatusCode::OK, Some("密码更新成功")));
+        assert!(room_password(&chat_manager, &room_id).await.unwrap().verify("new"));
+        let (status, _) = update(&app, request(None, json!({ "room_id": room_id, "new_password": "other", "current_password": "old" })).to_request()).await;
+        assert_eq!(status, StatusCode::FORBIDDEN);
+
+        let (status, _) = update(&app, request(Some(&token), json!({ "room_id": room_id, "new_password": "owner" })).to_request()).await;
+        assert_eq!(status, StatusCode::OK);
+        assert!(room_password(&chat_manager, &room_id).await.unwrap().verify("owner"));
+    }
+
+    #[actix_web::test]
+    async fn update_password_removes_and_rejects_blank_passwords() {
+        let (chat_manager, room_id, token) = password_room();
+        let app = test::init_service(
+            App::new().app_data(web::Data::new(chat_manager.clone())).service(update_password),
+        ).await;
+
+        let (status, body) = update(&app, request(Some(&token), json!({ "room_id": room_id, "new_password": "   " })).to_request()).await;
+        assert_eq!((status, body["code"].as_str()), (StatusCode::BAD_REQUEST, Some("PASSWORD_EMPTY")));
+
+        // 请求体中的 owner_token 兼容旧客户端
+        let (status, body) = update(&app, request(None, json!({ "room_id": room_id, "new_password": "", "owner_token": token })).to_request()).await;
+        assert_eq!((status, body["data"].as_str()), (StatusCode::OK, Some("密码已移除")));
+        assert!(room_password(&chat_manager, &room_id).await.is_none());
+
+        // 无密码的房间只有房主能设置密码
+        let (status, _) = update(&app, request(None, json!({ "room_id": room_id, "new_password": "new", "current_password": "" })).to_request()).await;
+        assert_eq!(status, StatusCode::FORBIDDEN);
+        let (status, _) = update(&app, request(Some(&token), json!({ "room_id": room_id, "new_password": "new" })).to_request()).await;
+        assert_eq!(status, StatusCode::OK);
+        assert!(room_password(&chat_manager, &room_id).await.unwrap().verify("new"));
+    }
+}
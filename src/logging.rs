@@ -0,0 +1,68 @@
+use actix_web::body::{BoxBody, MessageBody};
+use actix_web::dev::{ServiceRequest, ServiceResponse};
+use actix_web::http::header::{HeaderName, HeaderValue};
+use actix_web::middleware::{Logger, Next};
+use actix_web::{Error, HttpMessage};
+use std::io::IsTerminal;
+use tracing_subscriber::filter::{EnvFilter, LevelFilter};
+use uuid::Uuid;
+use crate::config::{LogConfig, LogFormat};
+
+pub const REQUEST_ID_HEADER: HeaderName = HeaderName::from_static("x-request-id");
+// 客户端或代理传入的请求 ID 的最大长度
+const MAX_REQUEST_ID_LEN: usize = 64;
+
+// 写入请求扩展，WebSocket 会话的日志携带同一个请求 ID
+#[derive(Debug, Clone)]
+pub struct RequestId(pub String);
+
+// 日志级别由 RUST_LOG 控制，未设置时为 info；log 宏输出的日志同样经过这里
+pub fn init(config: &LogConfig) -> Result<(), String> {
+    let filter = EnvFilter::builder()
+        .with_default_directive(LevelFilter::INFO.into())
+        .from_env()
+        .map_err(|e| format!("RUST_LOG 无效: {}", e))?;
+    let builder = tracing_subscriber::fmt()
+        .with_env_filter(filter)
+        .with_writer(std::io::stderr);
+
+    let result = match config.format {
+        LogFormat::Text => builder.with_ansi(std::io::stderr().is_terminal()).try_init(),
+        LogFormat::Json => builder.json().with_current_span(true).with_span_list(false).try_init(),
+    };
+    result.map_err(|e| format!("初始化日志失败: {}", e))
+}
+
+// actix 默认的访问日志格式加上请求 ID，探针和指标抓取不记录
+pub fn access_log() -> Logger {
+    Logger::new(r#"%a "%r" %s %b "%{Referer}i" "%{User-Agent}i" %T request_id=%{x-request-id}o"#)
+        .exclude("/metrics")
+        .exclude("/healthz")
+        .exclude("/readyz")
+}
+
+// 沿用传入的 X-Request-Id，缺失或格式不合法时生成新的，并在响应中返回
+pub async fn request_id(
+    req: ServiceRequest,
+    next: Next<impl MessageBody + 'static>,
+) -> Result<ServiceResponse<BoxBody>, Error> {
+    let id = req.headers()
+        .get(&REQUEST_ID_HEADER)
+        .and_then(|value| value.to_str().ok())
+        .filter(|id| is_valid_request_id(id))
+        .map(str::to_string)
+        .unwrap_or_else(|| Uuid::new_v4().to_string());
+    req.extensions_mut().insert(RequestId(id.clone()));
+
+    let mut response = next.call(req).await?.map_into_boxed_body();
+    if let Ok(value) = HeaderValue::from_str(&id) {
+        response.headers_mut().insert(REQUEST_ID_HEADER, value);
+    }
+    Ok(response)
+}
+
+fn is_valid_request_id(id: &str) -> bool {
+    !id.is_empty()
+        && id.len() <= MAX_REQUEST_ID_LEN
+        && id.bytes().all(|b| b.is_ascii_alphanumeric() || matches!(b, b'-' | b'_' | b'.'))
+}
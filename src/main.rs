@@ -1,35 +1,192 @@
 mod websocket;
 mod chatroom;
 mod handlers;
+mod ticket;
+mod store;
+mod ratelimit;
+mod validation;
+mod error;
+mod config;
+mod tls;
+mod metrics;
+mod shutdown;
+mod logging;
 
-use actix_web::{web, App, HttpServer, middleware::Logger};
+use actix_web::{web, App, HttpServer, middleware::from_fn};
 use std::sync::Arc;
+use std::time::Duration;
 use tokio::sync::RwLock;
 use chatroom::ChatRoomManager;
 use websocket::WebSocketManager;
+use ticket::TicketSigner;
+use ratelimit::RateLimiter;
+use error::ChatError;
+use config::Config;
+use tls::TlsReloader;
+use metrics::Metrics;
+use shutdown::Shutdown;
 
 #[actix_web::main]
 async fn main() -> std::io::Result<()> {
-    env_logger::init();
-    
-    let chat_manager = Arc::new(RwLock::new(ChatRoomManager::new()));
-    let ws_manager = Arc::new(RwLock::new(WebSocketManager::new()));
-    
-    HttpServer::new(move || {
+    let config = Arc::new(Config::load().map_err(std::io::Error::other)?);
+    logging::init(&config.log).map_err(std::io::Error::other)?;
+    let room_store = store::open(&config.store)
+        .map_err(std::io::Error::other)?;
+    let chat_manager = ChatRoomManager::with_store(room_store, &config.rooms)
+        .map_err(std::io::Error::other)?;
+    let metrics = Arc::new(Metrics::new());
+    let mut ws_manager = WebSocketManager::new(metrics.clone());
+    for (room_id, seq) in chat_manager.last_sequences().map_err(std::io::Error::other)? {
+        ws_manager.seed_sequence(room_id, seq);
+    }
+    let chat_manager = Arc::new(RwLock::new(chat_manager));
+    let ws_manager = Arc::new(RwLock::new(ws_manager));
+    let ticket_signer = Arc::new(TicketSigner::from_config(&config.ticket));
+    let rate_limiter = Arc::new(RateLimiter::new(&config));
+    let shutdown = Arc::new(Shutdown::new());
+
+    // 启用 TLS 时收到 SIGHUP 或证书文件变化会重新加载证书，已建立的连接不受影响；未启用时忽略 SIGHUP
+    let tls = match config.tls.paths() {
+        Some((cert_path, key_path)) => {
+            let tls = Arc::new(TlsReloader::new(cert_path, key_path).map_err(std::io::Error::other)?);
+            actix_web::rt::spawn(tls.clone().watch());
+            Some(tls)
+        }
+        None => {
+            actix_web::rt::spawn(tls::ignore_hangup());
+            None
+        }
+    };
+
+    // 定期回收最后一个用户离开后空闲超过 idle_ttl 秒的房间
+    let room_idle_ttl = config.rooms.idle_ttl().expect("idle_ttl 已在加载配置时校验");
+    {
+        let chat_manager = chat_manager.clone();
+        let ws_manager = ws_manager.clone();
+        actix_web::rt::spawn(async move {
+            // 第一次检查推迟一个周期，重启后给客户端留出重连的时间
+            let period = Duration::from_secs(60);
+            let mut interval = actix_web::rt::time::interval_at(actix_web::rt::time::Instant::now() + period, period);
+            loop {
+                interval.tick().await;
+                let mut manager = chat_manager.write().await;
+                let mut ws_manager = ws_manager.write().await;
+                for room_id in manager.reap_idle_rooms(&ws_manager, room_idle_ttl) {
+                    ws_manager.forget_room(&room_id);
+                    log::info!("回收空闲聊天室 {}", room_id);
+                }
+            }
+        });
+    }
+
+    // 定期清理限流器中长时间没有请求的 IP
+    {
+        let rate_limiter = rate_limiter.clone();
+        actix_web::rt::spawn(async move {
+            let mut interval = actix_web::rt::time::interval(Duration::from_secs(60));
+            loop {
+                interval.tick().await;
+                rate_limiter.prune();
+            }
+        });
+    }
+
+    // 设置 METRICS_PORT 时 /metrics 在单独的端口上提供，不与聊天服务共用
+    let metrics_config = config.metrics.clone();
+    let shared_metrics_route = metrics_config.enabled && metrics_config.port.is_none();
+    if let (true, Some(port)) = (metrics_config.enabled, metrics_config.port) {
+        let chat_manager = chat_manager.clone();
+        let ws_manager = ws_manager.clone();
+        let metrics = metrics.clone();
+        let metrics_server = HttpServer::new(move || {
+            App::new()
+                .app_data(web::Data::new(chat_manager.clone()))
+                .app_data(web::Data::new(ws_manager.clone()))
+                .app_data(web::Data::new(metrics.clone()))
+                .service(handlers::export_metrics)
+        })
+        .workers(1)
+        // 随聊天服务一起退出，不单独处理信号
+        .disable_signals()
+        .bind((config.server.host.as_str(), port))?
+        .run();
+        log::info!("监控指标监听 http://{}:{}/metrics", config.server.host, port);
+        actix_web::rt::spawn(metrics_server);
+    }
+
+    let server_config = config.server.clone();
+    let drain_shutdown = shutdown.clone();
+    let store_manager = chat_manager.clone();
+    let mut server = HttpServer::new(move || {
         App::new()
             .app_data(web::Data::new(chat_manager.clone()))
             .app_data(web::Data::new(ws_manager.clone()))
-            .wrap(Logger::default())
+            .app_data(web::Data::new(ticket_signer.clone()))
+            .app_data(web::Data::new(rate_limiter.clone()))
+            .app_data(web::Data::new(config.clone()))
+            .app_data(web::Data::new(metrics.clone()))
+            .app_data(web::Data::new(shutdown.clone()))
+            // 请求体和查询参数解析失败时同样返回带错误代码的 JSON
+            .app_data(web::JsonConfig::default()
+                .error_handler(|e, _| ChatError::InvalidRequest(e.to_string()).into()))
+            .app_data(web::QueryConfig::default()
+                .error_handler(|e, _| ChatError::InvalidRequest(e.to_string()).into()))
+            .wrap(from_fn(ratelimit::limit_api))
+            .wrap(from_fn(metrics::track_requests))
+            .wrap(from_fn(logging::request_id))
+            .wrap(logging::access_log())
             .service(handlers::index)
+            .service(handlers::healthz)
+            .service(handlers::readyz)
             .service(handlers::create_room)
             .service(handlers::list_rooms)
             .service(handlers::join_room)
             .service(handlers::update_password)
+            .service(handlers::rename_room)
+            .service(handlers::delete_room)
+            .service(handlers::kick_user)
+            .service(handlers::transfer_ownership)
+            .service(handlers::list_messages)
+            .service(handlers::list_members)
             .service(handlers::websocket_handler)
             .service(actix_web::web::resource("/static/{filename:.*}")
                 .route(actix_web::web::get().to(handlers::static_files)))
-    })
-    .bind("0.0.0.0:9099")?
-    .run()
-    .await
+            .configure(|cfg| {
+                if shared_metrics_route {
+                    cfg.service(handlers::export_metrics);
+                }
+            })
+    });
+    if let Some(workers) = server_config.workers {
+        server = server.workers(workers);
+    }
+    // 退出信号由 Shutdown 处理，先通知并关闭 WebSocket 会话再停止服务器
+    server = server
+        .disable_signals()
+        .shutdown_timeout(server_config.shutdown_timeout);
+
+    let address = (server_config.host.as_str(), server_config.port);
+    let server = match &tls {
+        Some(tls) => {
+            log::info!("聊天室服务器监听 https://{}:{}", server_config.host, server_config.port);
+            server.bind_rustls_0_23(address, tls.server_config().map_err(std::io::Error::other)?)?
+        }
+        None => {
+            log::info!("聊天室服务器监听 http://{}:{}", server_config.host, server_config.port);
+            server.bind(address)?
+        }
+    };
+    let server = server.run();
+    let handle = server.handle();
+    actix_web::rt::spawn(async move {
+        drain_shutdown.drain_on_signal(handle, server_config.shutdown_timeout()).await;
+    });
+    let result = server.await;
+
+    // 消息在后台线程中写入存储，退出前等待已提交的写入完成
+    let flushed = store_manager.read().await.flush_store();
+    if web::block(move || flushed.recv()).await.is_err() {
+        log::error!("等待消息写入存储失败");
+    }
+    result
 }
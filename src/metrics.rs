@@ -0,0 +1,110 @@
+use actix_web::body::{BoxBody, MessageBody};
+use actix_web::dev::{ServiceRequest, ServiceResponse};
+use actix_web::middleware::Next;
+use actix_web::{web, Error};
+use prometheus::{Encoder, Histogram, HistogramOpts, IntCounter, IntCounterVec, IntGauge, Opts, Registry, TextEncoder};
+use std::sync::Arc;
+use crate::error::ChatError;
+
+// 消息从管理器发出到会话写入连接的耗时分布（秒）
+const FANOUT_BUCKETS: &[f64] = &[0.0001, 0.00025, 0.0005, 0.001, 0.0025, 0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0];
+
+// Prometheus 指标，未开启 /metrics 时同样记录，只是不对外提供
+pub struct Metrics {
+    registry: Registry,
+    // 已建立的 WebSocket 连接，包括尚未加入房间的连接
+    pub websocket_sessions: IntGauge,
+    // 以下两项在抓取时从管理器读取
+    pub rooms: IntGauge,
+    pub active_rooms: IntGauge,
+    pub broadcasts: IntCounter,
+    pub chat_messages: IntCounter,
+    pub fanout_latency: Histogram,
+    pub heartbeat_timeouts: IntCounter,
+    pub join_failures: IntCounterVec,
+    pub http_requests: IntCounterVec,
+}
+
+impl Metrics {
+    pub fn new() -> Self {
+        let registry = Registry::new_custom(Some("chatroom".to_string()), None)
+            .expect("指标前缀有效");
+        let metrics = Self {
+            websocket_sessions: IntGauge::new("websocket_sessions", "当前打开的 WebSocket 连接数")
+                .expect("指标定义有效"),
+            rooms: IntGauge::new("rooms", "聊天室总数")
+                .expect("指标定义有效"),
+            active_rooms: IntGauge::new("active_rooms", "有成员在线或等待恢复的聊天室数")
+                .expect("指标定义有效"),
+            broadcasts: IntCounter::new("broadcasts_total", "向房间广播的事件数，包括聊天、加入离开、编辑和输入状态等")
+                .expect("指标定义有效"),
+            chat_messages: IntCounter::new("chat_messages_total", "广播的聊天消息数")
+                .expect("指标定义有效"),
+            fanout_latency: Histogram::with_opts(
+                HistogramOpts::new("broadcast_fanout_seconds", "消息从发出到各接收会话写入连接的耗时")
+                    .buckets(FANOUT_BUCKETS.to_vec()),
+            ).expect("指标定义有效"),
+            heartbeat_timeouts: IntCounter::new("heartbeat_timeouts_total", "心跳超时断开的连接数")
+                .expect("指标定义有效"),
+            join_failures: IntCounterVec::new(
+                Opts::new("join_failures_total", "加入聊天室失败次数，reason 为错误代码"),
+                &["transport", "reason"],
+            ).expect("指标定义有效"),
+            http_requests: IntCounterVec::new(
+                Opts::new("http_requests_total", "HTTP 请求数，path 为路由模板"),
+                &["method", "path", "status"],
+            ).expect("指标定义有效"),
+            registry,
+        };
+
+        let collectors: [Box<dyn prometheus::core::Collector>; 9] = [
+            Box::new(metrics.websocket_sessions.clone()),
+            Box::new(metrics.rooms.clone()),
+            Box::new(metrics.active_rooms.clone()),
+            Box::new(metrics.broadcasts.clone()),
+            Box::new(metrics.chat_messages.clone()),
+            Box::new(metrics.fanout_latency.clone()),
+            Box::new(metrics.heartbeat_timeouts.clone()),
+            Box::new(metrics.join_failures.clone()),
+            Box::new(metrics.http_requests.clone()),
+        ];
+        for collector in collectors {
+            metrics.registry.register(collector).expect("指标名称不重复");
+        }
+        metrics
+    }
+
+    // transport 为 http（POST /api/rooms/join）或 websocket（升级、join 和 resume）
+    pub fn join_failed(&self, transport: &str, error: &ChatError) {
+        self.join_failures.with_label_values(&[transport, error.code()]).inc();
+    }
+
+    // Prometheus 文本格式
+    pub fn render(&self) -> Result<String, ChatError> {
+        let mut buffer = Vec::new();
+        TextEncoder::new()
+            .encode(&self.registry.gather(), &mut buffer)
+            .map_err(|e| ChatError::Internal(format!("导出监控指标失败: {}", e)))?;
+        String::from_utf8(buffer).map_err(|e| ChatError::Internal(format!("导出监控指标失败: {}", e)))
+    }
+}
+
+// 按方法、路由模板和状态码统计请求，未匹配路由的请求记为 unmatched，避免路径基数过大
+pub async fn track_requests(
+    req: ServiceRequest,
+    next: Next<impl MessageBody + 'static>,
+) -> Result<ServiceResponse<BoxBody>, Error> {
+    let metrics = req.app_data::<web::Data<Arc<Metrics>>>().cloned();
+    let method = req.method().to_string();
+    let path = req.match_pattern().unwrap_or_else(|| "unmatched".to_string());
+
+    let response = next.call(req).await.map(ServiceResponse::map_into_boxed_body);
+    if let Some(metrics) = metrics {
+        let status = match &response {
+            Ok(response) => response.status(),
+            Err(e) => e.as_response_error().status_code(),
+        };
+        metrics.http_requests.with_label_values(&[method.as_str(), path.as_str(), status.as_str()]).inc();
+    }
+    response
+}
@@ -0,0 +1,385 @@
+use actix_web::body::{BoxBody, MessageBody};
+use actix_web::dev::{ServiceRequest, ServiceResponse};
+use actix_web::http::{header, Method};
+use actix_web::middleware::Next;
+use actix_web::{web, Error, HttpRequest, ResponseError};
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use serde::Deserialize;
+use crate::config::Config;
+use crate::error::ChatError;
+
+// 反向代理用客户端地址覆盖的请求头
+const REAL_IP_HEADER: &str = "x-real-ip";
+// 统计 WebSocket 违规次数的时间窗口
+const VIOLATION_WINDOW: Duration = Duration::from_secs(60);
+
+// 令牌桶参数：每秒补充 rate 个令牌，最多积累 burst 个；rate 为 0 表示不限流
+#[derive(Debug, Clone, Copy, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct RateLimit {
+    pub rate: f64,
+    pub burst: f64,
+}
+
+#[derive(Debug)]
+pub struct TokenBucket {
+    limit: RateLimit,
+    tokens: f64,
+    updated: Instant,
+}
+
+impl TokenBucket {
+    pub fn new(limit: RateLimit) -> Self {
+        Self {
+            limit,
+            tokens: limit.burst,
+            updated: Instant::now(),
+        }
+    }
+
+    fn refill(&mut self) {
+        let now = Instant::now();
+        let elapsed = now.duration_since(self.updated).as_secs_f64();
+        self.tokens = (self.tokens + elapsed * self.limit.rate).min(self.limit.burst);
+        self.updated = now;
+    }
+
+    pub fn try_acquire(&mut self) -> bool {
+        if self.limit.rate <= 0.0 {
+            return true;
+        }
+        self.refill();
+        if self.tokens >= 1.0 {
+            self.tokens -= 1.0;
+            true
+        } else {
+            false
+        }
+    }
+
+    // 桶已补满说明该客户端近期没有请求，可以丢弃
+    fn is_idle(&mut self) -> bool {
+        self.refill();
+        self.tokens >= self.limit.burst
+    }
+
+    // 下一个令牌可用前需要等待的秒数
+    fn retry_after(&self) -> u64 {
+        if self.limit.rate <= 0.0 {
+            return 0;
+        }
+        ((1.0 - self.tokens).max(0.0) / self.limit.rate).ceil() as u64
+    }
+}
+
+pub enum Throttle {
+    Allowed,
+    // 超出速率，本条消息被丢弃
+    Limited,
+    // 窗口内违规次数过多，应断开连接
+    Exceeded,
+}
+
+// 当前打开的 WebSocket 连接数
+#[derive(Debug, Default)]
+struct ConnectionCounts {
+    total: usize,
+    per_ip: HashMap<IpAddr, usize>,
+}
+
+// 占用一个连接名额，随会话一起释放
+#[derive(Debug)]
+struct ConnectionPermit {
+    counts: Arc<Mutex<ConnectionCounts>>,
+    ip: Option<IpAddr>,
+}
+
+impl Drop for ConnectionPermit {
+    fn drop(&mut self) {
+        let Ok(mut counts) = self.counts.lock() else {
+            return;
+        };
+        counts.total = counts.total.saturating_sub(1);
+        if let Some(ip) = self.ip {
+            if let Some(count) = counts.per_ip.get_mut(&ip) {
+                *count -= 1;
+                if *count == 0 {
+                    counts.per_ip.remove(&ip);
+                }
+            }
+        }
+    }
+}
+
+// 单个 WebSocket 连接的消息限流
+#[derive(Debug)]
+pub struct ConnectionLimiter {
+    bucket: TokenBucket,
+    max_violations: u32,
+    violations: u32,
+    window_start: Instant,
+    permit: ConnectionPermit,
+}
+
+impl ConnectionLimiter {
+    // 计入连接数的客户端 IP，不信任代理且无法取得对端地址时为空
+    pub fn ip(&self) -> Option<IpAddr> {
+        self.permit.ip
+    }
+
+    pub fn check(&mut self) -> Throttle {
+        if self.bucket.try_acquire() {
+            return Throttle::Allowed;
+        }
+        self.violation()
+    }
+
+    // 记录一次违规（超出速率或加入、恢复失败），窗口内次数过多时返回 Exceeded
+    pub fn violation(&mut self) -> Throttle {
+        if self.window_start.elapsed() > VIOLATION_WINDOW {
+            self.window_start = Instant::now();
+            self.violations = 0;
+        }
+        self.violations += 1;
+        if self.max_violations > 0 && self.violations >= self.max_violations {
+            Throttle::Exceeded
+        } else {
+            Throttle::Limited
+        }
+    }
+}
+
+// 按客户端 IP 限制 /api 请求，创建房间另有更严格的限制；同时限制 WebSocket 连接总数和每个 IP 的连接数
+#[derive(Debug)]
+pub struct RateLimiter {
+    chat: RateLimit,
+    chat_max_violations: u32,
+    api: RateLimit,
+    create_room: RateLimit,
+    // 部署在反向代理之后时从 X-Real-IP 头取客户端 IP
+    trust_proxy: bool,
+    api_buckets: Mutex<HashMap<IpAddr, TokenBucket>>,
+    create_room_buckets: Mutex<HashMap<IpAddr, TokenBucket>>,
+    max_connections: usize,
+    max_connections_per_ip: usize,
+    connections: Arc<Mutex<ConnectionCounts>>,
+}
+
+impl RateLimiter {
+    pub fn new(config: &Config) -> Self {
+        let limits = &config.rate_limit;
+        Self {
+            chat: limits.chat,
+            chat_max_violations: limits.chat_max_violations,
+            api: limits.api,
+            create_room: limits.room_create,
+            trust_proxy: limits.trust_proxy,
+            api_buckets: Mutex::new(HashMap::new()),
+            create_room_buckets: Mutex::new(HashMap::new()),
+            max_connections: config.server.max_connections,
+            max_connections_per_ip: config.server.max_connections_per_ip,
+            connections: Arc::new(Mutex::new(ConnectionCounts::default())),
+        }
+    }
+
+    // 为新的 WebSocket 连接占用名额，连接数已满时拒绝
+    pub fn connection_limiter(&self, req: &HttpRequest) -> Result<ConnectionLimiter, ChatError> {
+        let ip = self.client_ip(req);
+        let mut counts = self.connections.lock()
+            .map_err(|_| ChatError::Internal("连接计数不可用".to_string()))?;
+        if counts.total >= self.max_connections {
+            return Err(ChatError::ServerFull);
+        }
+        if let Some(ip) = ip {
+            let count = counts.per_ip.entry(ip).or_insert(0);
+            if *count >= self.max_connections_per_ip {
+                return Err(ChatError::TooManyConnections);
+            }
+            *count += 1;
+        }
+        counts.total += 1;
+
+        Ok(ConnectionLimiter {
+            bucket: TokenBucket::new(self.chat),
+            max_violations: self.chat_max_violations,
+            violations: 0,
+            window_start: Instant::now(),
+            permit: ConnectionPermit { counts: self.connections.clone(), ip },
+        })
+    }
+
+    // 只信任代理覆盖写入的 X-Real-IP；X-Forwarded-For 和 Forwarded 会保留客户端自己传入的值，不能用于限流
+    fn client_ip(&self, req: &HttpRequest) -> Option<IpAddr> {
+        if self.trust_proxy {
+            if let Some(ip) = req.headers().get(REAL_IP_HEADER)
+                .and_then(|value| value.to_str().ok())
+                .and_then(|value| value.trim().parse::<IpAddr>().ok())
+            {
+                return Some(ip);
+            }
+        }
+        req.peer_addr().map(|addr| addr.ip())
+    }
+
+    // 通过时返回 None，被限流时返回建议的重试秒数
+    fn check(&self, ip: IpAddr, create_room: bool) -> Option<u64> {
+        let mut buckets = self.api_buckets.lock().ok()?;
+        let bucket = buckets.entry(ip).or_insert_with(|| TokenBucket::new(self.api));
+        if !bucket.try_acquire() {
+            return Some(bucket.retry_after());
+        }
+
+        if create_room {
+            let mut buckets = self.create_room_buckets.lock().ok()?;
+            let bucket = buckets.entry(ip).or_insert_with(|| TokenBucket::new(self.create_room));
+            if !bucket.try_acquire() {
+                return Some(bucket.retry_after());
+            }
+        }
+        None
+    }
+
+    // 清理近期没有请求的 IP
+    pub fn prune(&self) {
+        for buckets in [&self.api_buckets, &self.create_room_buckets] {
+            if let Ok(mut buckets) = buckets.lock() {
+                buckets.retain(|_, bucket| !bucket.is_idle());
+            }
+        }
+    }
+}
+
+// /api 路由的限流中间件，超出限制返回 429
+pub async fn limit_api(
+    req: ServiceRequest,
+    next: Next<impl MessageBody + 'static>,
+) -> Result<ServiceResponse<BoxBody>, Error> {
+    if req.path().starts_with("/api/") {
+        if let Some(limiter) = req.app_data::<web::Data<Arc<RateLimiter>>>().cloned() {
+            let create_room = req.method() == Method::POST && req.path() == "/api/rooms";
+            let retry_after = limiter.client_ip(req.request())
+                .and_then(|ip| limiter.check(ip, create_room));
+            if let Some(retry_after) = retry_after {
+                let mut response = ChatError::RateLimited.error_response();
+                response.headers_mut().insert(header::RETRY_AFTER, header::HeaderValue::from(retry_after.max(1)));
+                return Ok(req.into_response(response));
+            }
+        }
+    }
+
+    next.call(req).await.map(ServiceResponse::map_into_boxed_body)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actix_web::test::TestRequest;
+
+    // 把桶的更新时间往前拨，模拟时间流逝
+    fn advance(bucket: &mut TokenBucket, elapsed: Duration) {
+        bucket.updated -= elapsed;
+    }
+
+    #[test]
+    fn bucket_refills_at_rate_up_to_burst() {
+        let mut bucket = TokenBucket::new(RateLimit { rate: 10.0, burst: 2.0 });
+        assert!(bucket.try_acquire());
+        assert!(bucket.try_acquire());
+        assert!(!bucket.try_acquire());
+        assert_eq!(bucket.retry_after(), 1);
+
+        advance(&mut bucket, Duration::from_millis(150));
+        assert!(bucket.try_acquire());
+        assert!(!bucket.try_acquire());
+
+        // 空闲再久也不会超过突发上限
+        advance(&mut bucket, Duration::from_secs(60));
+        assert!(bucket.is_idle());
+        assert!(bucket.try_acquire());
+        assert!(bucket.try_acquire());
+        assert!(!bucket.try_acquire());
+    }
+
+    #[test]
+    fn zero_rate_is_unlimited() {
+        let mut bucket = TokenBucket::new(RateLimit { rate: 0.0, burst: 1.0 });
+        for _ in 0..100 {
+            assert!(bucket.try_acquire());
+        }
+    }
+
+    fn connection_limiter(max_violations: u32) -> ConnectionLimiter {
+        let mut config = Config::default();
+        config.rate_limit.chat = RateLimit { rate: 0.001, burst: 1.0 };
+        config.rate_limit.chat_max_violations = max_violations;
+        RateLimiter::new(&config)
+            .connection_limiter(&TestRequest::default().to_http_request())
+            .unwrap()
+    }
+
+    #[test]
+    fn violations_escalate_to_disconnect() {
+        let mut limiter = connection_limiter(3);
+        assert!(matches!(limiter.check(), Throttle::Allowed));
+        assert!(matches!(limiter.check(), Throttle::Limited));
+        assert!(matches!(limiter.check(), Throttle::Limited));
+        assert!(matches!(limiter.check(), Throttle::Exceeded));
+    }
+
+    #[test]
+    fn failed_attempts_count_as_violations() {
+        let mut limiter = connection_limiter(3);
+        assert!(matches!(limiter.violation(), Throttle::Limited));
+        assert!(matches!(limiter.check(), Throttle::Allowed));
+        assert!(matches!(limiter.violation(), Throttle::Limited));
+        assert!(matches!(limiter.violation(), Throttle::Exceeded));
+    }
+
+    #[test]
+    fn violations_reset_after_window() {
+        let mut limiter = connection_limiter(3);
+        assert!(matches!(limiter.check(), Throttle::Allowed));
+        assert!(matches!(limiter.check(), Throttle::Limited));
+        assert!(matches!(limiter.check(), Throttle::Limited));
+
+        limiter.window_start -= VIOLATION_WINDOW + Duration::from_secs(1);
+        assert!(matches!(limiter.check(), Throttle::Limited));
+        assert!(matches!(limiter.check(), Throttle::Limited));
+        assert!(matches!(limiter.check(), Throttle::Exceeded));
+    }
+
+    #[test]
+    fn connection_permit_is_released_on_drop() {
+        let mut config = Config::default();
+        config.server.max_connections = 1;
+        let limiter = RateLimiter::new(&config);
+        let req = TestRequest::default().to_http_request();
+
+        let permit = limiter.connection_limiter(&req).unwrap();
+        assert!(matches!(limiter.connection_limiter(&req), Err(ChatError::ServerFull)));
+        drop(permit);
+        assert!(limiter.connection_limiter(&req).is_ok());
+    }
+
+    #[test]
+    fn client_ip_reads_only_real_ip_header_behind_proxy() {
+        let mut config = Config::default();
+        config.rate_limit.trust_proxy = true;
+        let limiter = RateLimiter::new(&config);
+
+        let req = TestRequest::default()
+            .insert_header(("X-Forwarded-For", "10.0.0.1"))
+            .insert_header(("X-Real-IP", "192.0.2.7"))
+            .to_http_request();
+        assert_eq!(limiter.client_ip(&req), Some("192.0.2.7".parse().unwrap()));
+
+        let req = TestRequest::default()
+            .insert_header(("X-Forwarded-For", "10.0.0.1"))
+            .peer_addr("198.51.100.1:4000".parse().unwrap())
+            .to_http_request();
+        assert_eq!(limiter.client_ip(&req), Some("198.51.100.1".parse().unwrap()));
+    }
+}
@@ -0,0 +1,60 @@
+use actix_web::dev::ServerHandle;
+use std::time::Duration;
+use tokio::signal::unix::{signal, SignalKind};
+use tokio::sync::watch;
+
+// 检查会话是否已全部关闭的间隔
+const DRAIN_POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+// 收到 SIGTERM 或 SIGINT 后进入排空状态：拒绝新的 WebSocket 升级，通知并关闭已有会话
+#[derive(Debug)]
+pub struct Shutdown {
+    draining: watch::Sender<bool>,
+}
+
+impl Shutdown {
+    pub fn new() -> Self {
+        Self { draining: watch::Sender::new(false) }
+    }
+
+    pub fn is_draining(&self) -> bool {
+        *self.draining.borrow()
+    }
+
+    // 每个会话持有一个接收端，接收端数量即仍打开的会话数
+    pub fn subscribe(&self) -> watch::Receiver<bool> {
+        self.draining.subscribe()
+    }
+
+    // 等待退出信号，排空会话后停止服务器；超时仍未关闭的连接直接断开
+    pub async fn drain_on_signal(&self, server: ServerHandle, timeout: Duration) {
+        if let Err(e) = wait_for_signal().await {
+            log::error!("无法监听退出信号: {}", e);
+            return;
+        }
+
+        log::info!("收到退出信号，停止接受新连接并通知 {} 个会话", self.draining.receiver_count());
+        self.draining.send_replace(true);
+
+        let deadline = tokio::time::Instant::now() + timeout;
+        while self.draining.receiver_count() > 0 && tokio::time::Instant::now() < deadline {
+            actix_web::rt::time::sleep(DRAIN_POLL_INTERVAL).await;
+        }
+
+        let remaining = self.draining.receiver_count();
+        if remaining > 0 {
+            log::warn!("等待超时，强制关闭剩余的 {} 个会话", remaining);
+        }
+        server.stop(remaining == 0).await;
+    }
+}
+
+async fn wait_for_signal() -> std::io::Result<()> {
+    let mut terminate = signal(SignalKind::terminate())?;
+    let mut interrupt = signal(SignalKind::interrupt())?;
+    tokio::select! {
+        _ = terminate.recv() => {}
+        _ = interrupt.recv() => {}
+    }
+    Ok(())
+}
@@ -0,0 +1,516 @@
+use std::fmt;
+use std::sync::{mpsc, Arc, Mutex};
+use rusqlite::{params, Connection};
+use crate::chatroom::{ChatRoom, HashedPassword, OwnerToken};
+use crate::websocket::ChatMessage;
+use crate::config::{StoreBackend, StoreConfig};
+
+// 房间持久化接口，ChatRoomManager 在内存中保留全部房间，修改时经 StoreWriter 写入存储
+pub trait RoomStore: Send + Sync + fmt::Debug {
+    // 是否在重启后保留数据
+    fn is_persistent(&self) -> bool;
+    fn load_rooms(&self) -> Result<Vec<ChatRoom>, String>;
+    fn save_room(&self, room: &ChatRoom) -> Result<(), String>;
+    fn delete_room(&self, room_id: &str) -> Result<(), String>;
+    // 同时记录房间最后分配的序号
+    fn save_message(&self, room_id: &str, message: &ChatMessage) -> Result<(), String>;
+    // 按房间内序号覆盖或删除已保存的消息
+    fn update_message(&self, room_id: &str, message: &ChatMessage) -> Result<(), String>;
+    fn delete_message(&self, room_id: &str, seq: u64) -> Result<(), String>;
+    // 返回序号小于 before 的最近最多 limit 条消息，按序号升序
+    fn load_messages(&self, room_id: &str, before: Option<u64>, limit: usize) -> Result<Vec<ChatMessage>, String>;
+    // 各房间最后分配的消息序号，最新的消息被删除后也不会回退
+    fn load_sequences(&self) -> Result<Vec<(String, u64)>, String>;
+}
+
+// 纯内存模式，不做任何持久化，重启后房间清空
+#[derive(Debug, Default)]
+pub struct MemoryRoomStore;
+
+impl RoomStore for MemoryRoomStore {
+    fn is_persistent(&self) -> bool {
+        false
+    }
+
+    fn load_rooms(&self) -> Result<Vec<ChatRoom>, String> {
+        Ok(Vec::new())
+    }
+
+    fn save_room(&self, _room: &ChatRoom) -> Result<(), String> {
+        Ok(())
+    }
+
+    fn delete_room(&self, _room_id: &str) -> Result<(), String> {
+        Ok(())
+    }
+
+    fn save_message(&self, _room_id: &str, _message: &ChatMessage) -> Result<(), String> {
+        Ok(())
+    }
+
+    fn update_message(&self, _room_id: &str, _message: &ChatMessage) -> Result<(), String> {
+        Ok(())
+    }
+
+    fn delete_message(&self, _room_id: &str, _seq: u64) -> Result<(), String> {
+        Ok(())
+    }
+
+    fn load_messages(&self, _room_id: &str, _before: Option<u64>, _limit: usize) -> Result<Vec<ChatMessage>, String> {
+        Ok(Vec::new())
+    }
+
+    fn load_sequences(&self) -> Result<Vec<(String, u64)>, String> {
+        Ok(Vec::new())
+    }
+}
+
+// 嵌入式 SQLite 存储，房间、房主令牌摘要和密码哈希在重启后保留
+#[derive(Debug)]
+pub struct SqliteRoomStore {
+    conn: Mutex<Connection>,
+}
+
+impl SqliteRoomStore {
+    pub fn open(path: &str) -> Result<Self, String> {
+        let conn = Connection::open(path)
+            .map_err(|e| format!("无法打开数据库 {}: {}", path, e))?;
+
+        conn.execute_batch(
+            "CREATE TABLE IF NOT EXISTS rooms (
+                id TEXT PRIMARY KEY,
+                name TEXT NOT NULL,
+                password_hash TEXT,
+                owner_token_hash BLOB NOT NULL,
+                created_at TEXT NOT NULL,
+                last_active_at TEXT NOT NULL
+            );
+            CREATE TABLE IF NOT EXISTS messages (
+                id INTEGER PRIMARY KEY AUTOINCREMENT,
+                room_id TEXT NOT NULL,
+                timestamp_us INTEGER NOT NULL,
+                payload TEXT NOT NULL
+            );
+            CREATE INDEX IF NOT EXISTS idx_messages_room ON messages (room_id, timestamp_us);",
+        )
+        .map_err(|e| format!("初始化数据库失败: {}", e))?;
+
+        add_column_if_missing(&conn, "rooms", "history_enabled", "INTEGER NOT NULL DEFAULT 0")?;
+        add_column_if_missing(&conn, "messages", "seq", "INTEGER NOT NULL DEFAULT 0")?;
+        add_column_if_missing(&conn, "rooms", "max_members", "INTEGER NOT NULL DEFAULT 0")?;
+        add_column_if_missing(&conn, "rooms", "last_seq", "INTEGER NOT NULL DEFAULT 0")?;
+        conn.execute_batch("CREATE INDEX IF NOT EXISTS idx_messages_seq ON messages (room_id, seq);")
+            .map_err(|e| format!("初始化数据库失败: {}", e))?;
+        // 旧版本没有 last_seq 列，用已保存的消息补齐
+        conn.execute_batch(
+            "UPDATE rooms SET last_seq = MAX(last_seq, (SELECT COALESCE(MAX(seq), 0) FROM messages WHERE messages.room_id = rooms.id));",
+        )
+        .map_err(|e| format!("初始化数据库失败: {}", e))?;
+
+        Ok(Self { conn: Mutex::new(conn) })
+    }
+}
+
+// 旧版本数据库缺少的列在启动时补齐
+fn add_column_if_missing(conn: &Connection, table: &str, column: &str, definition: &str) -> Result<(), String> {
+    let exists = conn
+        .prepare(&format!("SELECT 1 FROM pragma_table_info('{}') WHERE name = ?1", table))
+        .and_then(|mut stmt| stmt.exists(params![column]))
+        .map_err(|e| format!("初始化数据库失败: {}", e))?;
+
+    if !exists {
+        conn.execute_batch(&format!("ALTER TABLE {} ADD COLUMN {} {};", table, column, definition))
+            .map_err(|e| format!("初始化数据库失败: {}", e))?;
+    }
+    Ok(())
+}
+
+impl RoomStore for SqliteRoomStore {
+    fn is_persistent(&self) -> bool {
+        true
+    }
+
+    fn load_rooms(&self) -> Result<Vec<ChatRoom>, String> {
+        let conn = self.conn.lock().map_err(|_| "数据库连接不可用")?;
+        let mut stmt = conn
+            .prepare("SELECT id, name, password_hash, owner_token_hash, created_at, last_active_at, history_enabled, max_members FROM rooms")
+            .map_err(|e| e.to_string())?;
+
+        let rooms = stmt
+            .query_map([], |row| {
+                let password_hash: Option<String> = row.get(2)?;
+                let owner_token_hash: Vec<u8> = row.get(3)?;
+                Ok(ChatRoom {
+                    id: row.get(0)?,
+                    name: row.get(1)?,
+                    password: password_hash.map(HashedPassword::from_phc),
+                    owner_token: OwnerToken::from_digest(&owner_token_hash),
+                    history_enabled: row.get(6)?,
+                    max_members: row.get(7)?,
+                    created_at: row.get(4)?,
+                    last_active_at: row.get(5)?,
+                })
+            })
+            .map_err(|e| e.to_string())?
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(|e| e.to_string())?;
+
+        Ok(rooms)
+    }
+
+    fn save_room(&self, room: &ChatRoom) -> Result<(), String> {
+        let conn = self.conn.lock().map_err(|_| "数据库连接不可用")?;
+        conn.execute(
+            "INSERT INTO rooms (id, name, password_hash, owner_token_hash, created_at, last_active_at, history_enabled, max_members)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8)
+             ON CONFLICT(id) DO UPDATE SET
+                name = excluded.name,
+                password_hash = excluded.password_hash,
+                owner_token_hash = excluded.owner_token_hash,
+                last_active_at = excluded.last_active_at,
+                history_enabled = excluded.history_enabled,
+                max_members = excluded.max_members",
+            params![
+                room.id,
+                room.name,
+                room.password.as_ref().map(HashedPassword::as_phc),
+                room.owner_token.digest().as_slice(),
+                room.created_at,
+                room.last_active_at,
+                room.history_enabled,
+                room.max_members,
+            ],
+        )
+        .map_err(|e| e.to_string())?;
+        Ok(())
+    }
+
+    fn delete_room(&self, room_id: &str) -> Result<(), String> {
+        let conn = self.conn.lock().map_err(|_| "数据库连接不可用")?;
+        conn.execute("DELETE FROM rooms WHERE id = ?1", params![room_id])
+            .map_err(|e| e.to_string())?;
+        conn.execute("DELETE FROM messages WHERE room_id = ?1", params![room_id])
+            .map_err(|e| e.to_string())?;
+        Ok(())
+    }
+
+    fn save_message(&self, room_id: &str, message: &ChatMessage) -> Result<(), String> {
+        let payload = serde_json::to_string(message).map_err(|e| e.to_string())?;
+        let conn = self.conn.lock().map_err(|_| "数据库连接不可用")?;
+        conn.execute(
+            "INSERT INTO messages (room_id, seq, timestamp_us, payload) VALUES (?1, ?2, ?3, ?4)",
+            params![room_id, message.seq as i64, message.timestamp.timestamp_micros(), payload],
+        )
+        .map_err(|e| e.to_string())?;
+        conn.execute(
+            "UPDATE rooms SET last_seq = MAX(last_seq, ?2) WHERE id = ?1",
+            params![room_id, message.seq as i64],
+        )
+        .map_err(|e| e.to_string())?;
+        Ok(())
+    }
+
+    fn update_message(&self, room_id: &str, message: &ChatMessage) -> Result<(), String> {
+        let payload = serde_json::to_string(message).map_err(|e| e.to_string())?;
+        let conn = self.conn.lock().map_err(|_| "数据库连接不可用")?;
+        conn.execute(
+            "UPDATE messages SET payload = ?3 WHERE room_id = ?1 AND seq = ?2",
+            params![room_id, message.seq as i64, payload],
+        )
+        .map_err(|e| e.to_string())?;
+        Ok(())
+    }
+
+    fn delete_message(&self, room_id: &str, seq: u64) -> Result<(), String> {
+        let conn = self.conn.lock().map_err(|_| "数据库连接不可用")?;
+        conn.execute("DELETE FROM messages WHERE room_id = ?1 AND seq = ?2", params![room_id, seq as i64])
+            .map_err(|e| e.to_string())?;
+        Ok(())
+    }
+
+    fn load_messages(&self, room_id: &str, before: Option<u64>, limit: usize) -> Result<Vec<ChatMessage>, String> {
+        let conn = self.conn.lock().map_err(|_| "数据库连接不可用")?;
+        let mut stmt = conn
+            .prepare(
+                "SELECT payload FROM messages
+                 WHERE room_id = ?1 AND (?2 IS NULL OR seq < ?2)
+                 ORDER BY seq DESC, id DESC LIMIT ?3",
+            )
+            .map_err(|e| e.to_string())?;
+
+        let payloads = stmt
+            .query_map(
+                params![room_id, before.map(|before| before as i64), limit as i64],
+                |row| row.get::<_, String>(0),
+            )
+            .map_err(|e| e.to_string())?
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(|e| e.to_string())?;
+
+        let mut messages = payloads
+            .iter()
+            .map(|payload| serde_json::from_str(payload).map_err(|e| e.to_string()))
+            .collect::<Result<Vec<ChatMessage>, String>>()?;
+        messages.reverse();
+        Ok(messages)
+    }
+
+    fn load_sequences(&self) -> Result<Vec<(String, u64)>, String> {
+        let conn = self.conn.lock().map_err(|_| "数据库连接不可用")?;
+        let mut stmt = conn
+            .prepare("SELECT id, last_seq FROM rooms WHERE last_seq > 0")
+            .map_err(|e| e.to_string())?;
+
+        let sequences = stmt
+            .query_map([], |row| Ok((row.get::<_, String>(0)?, row.get::<_, i64>(1)? as u64)))
+            .map_err(|e| e.to_string())?
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(|e| e.to_string())?;
+        Ok(sequences)
+    }
+}
+
+enum StoreWrite {
+    SaveRoom(ChatRoom),
+    SaveMessage(String, ChatMessage),
+    UpdateMessage(String, ChatMessage),
+    DeleteMessage(String, u64),
+    DeleteRoom(String),
+    // 之前提交的写入全部完成后通知
+    Flush(mpsc::Sender<()>),
+}
+
+// 房间和消息的写入、修改、删除在单独的线程中按提交顺序执行，
+// 不占用处理请求的线程，也不在持有管理器锁期间等待磁盘 I/O；失败时只记录日志
+#[derive(Debug)]
+pub struct StoreWriter {
+    sender: mpsc::Sender<StoreWrite>,
+}
+
+impl StoreWriter {
+    pub fn spawn(store: Arc<dyn RoomStore>) -> Result<Self, String> {
+        let (sender, receiver) = mpsc::channel();
+        std::thread::Builder::new()
+            .name("store-writer".to_string())
+            .spawn(move || {
+                for write in receiver {
+                    apply(store.as_ref(), write);
+                }
+            })
+            .map_err(|e| format!("无法启动存储写入线程: {}", e))?;
+        Ok(Self { sender })
+    }
+
+    pub fn save_room(&self, room: &ChatRoom) {
+        self.submit(StoreWrite::SaveRoom(room.clone()));
+    }
+
+    pub fn save_message(&self, room_id: &str, message: &ChatMessage) {
+        self.submit(StoreWrite::SaveMessage(room_id.to_string(), message.clone()));
+    }
+
+    pub fn update_message(&self, room_id: &str, message: &ChatMessage) {
+        self.submit(StoreWrite::UpdateMessage(room_id.to_string(), message.clone()));
+    }
+
+    pub fn delete_message(&self, room_id: &str, seq: u64) {
+        self.submit(StoreWrite::DeleteMessage(room_id.to_string(), seq));
+    }
+
+    pub fn delete_room(&self, room_id: &str) {
+        self.submit(StoreWrite::DeleteRoom(room_id.to_string()));
+    }
+
+    // 返回的接收端在此前提交的写入全部完成后收到通知，退出前用于等待写入完成
+    pub fn flush(&self) -> mpsc::Receiver<()> {
+        let (done, receiver) = mpsc::channel();
+        self.submit(StoreWrite::Flush(done));
+        receiver
+    }
+
+    fn submit(&self, write: StoreWrite) {
+        if self.sender.send(write).is_err() {
+            log::error!("存储写入线程已退出，写入被丢弃");
+        }
+    }
+}
+
+fn apply(store: &dyn RoomStore, write: StoreWrite) {
+    let result = match write {
+        StoreWrite::SaveRoom(room) => store.save_room(&room)
+            .map_err(|e| format!("保存聊天室 {} 失败: {}", room.id, e)),
+        StoreWrite::SaveMessage(room_id, message) => store.save_message(&room_id, &message)
+            .map_err(|e| format!("保存聊天室 {} 的消息失败: {}", room_id, e)),
+        StoreWrite::UpdateMessage(room_id, message) => store.update_message(&room_id, &message)
+            .map_err(|e| format!("更新聊天室 {} 的消息失败: {}", room_id, e)),
+        StoreWrite::DeleteMessage(room_id, seq) => store.delete_message(&room_id, seq)
+            .map_err(|e| format!("删除聊天室 {} 的消息失败: {}", room_id, e)),
+        StoreWrite::DeleteRoom(room_id) => store.delete_room(&room_id)
+            .map_err(|e| format!("删除聊天室 {} 的存储记录失败: {}", room_id, e)),
+        StoreWrite::Flush(done) => {
+            let _ = done.send(());
+            Ok(())
+        }
+    };
+    if let Err(e) = result {
+        log::error!("{}", e);
+    }
+}
+
+// 根据配置的存储后端（memory / sqlite）打开存储
+pub fn open(config: &StoreConfig) -> Result<Box<dyn RoomStore>, String> {
+    match config.backend {
+        StoreBackend::Sqlite => Ok(Box::new(SqliteRoomStore::open(&config.path)?)),
+        StoreBackend::Memory => Ok(Box::new(MemoryRoomStore)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn message(seq: u64, content: &str) -> ChatMessage {
+        ChatMessage {
+            id: format!("message-{}", seq),
+            seq,
+            content: content.to_string(),
+            username: "alice".to_string(),
+            timestamp: chrono::Utc::now(),
+            user_id: "user".to_string(),
+            edited_at: None,
+            reply_to: None,
+            reactions: Vec::new(),
+        }
+    }
+
+    fn contents(messages: &[ChatMessage]) -> Vec<&str> {
+        messages.iter().map(|message| message.content.as_str()).collect()
+    }
+
+    #[test]
+    fn sqlite_round_trips_rooms() {
+        let store = SqliteRoomStore::open(":memory:").unwrap();
+        let (owner_token, token) = OwnerToken::generate();
+        let mut room = ChatRoom::new("room".to_string(), Some(HashedPassword::new("secret").unwrap()), owner_token, true, 10);
+        store.save_room(&room).unwrap();
+
+        room.name = "renamed".to_string();
+        store.save_room(&room).unwrap();
+
+        let rooms = store.load_rooms().unwrap();
+        assert_eq!(rooms.len(), 1);
+        let loaded = &rooms[0];
+        assert_eq!(loaded.id, room.id);
+        assert_eq!(loaded.name, "renamed");
+        assert!(loaded.history_enabled);
+        assert_eq!(loaded.max_members, 10);
+        assert!(loaded.is_owner(&token));
+        assert!(loaded.password.as_ref().unwrap().verify("secret"));
+
+        store.delete_room(&room.id).unwrap();
+        assert!(store.load_rooms().unwrap().is_empty());
+    }
+
+    #[test]
+    fn sqlite_round_trips_messages() {
+        let store = SqliteRoomStore::open(":memory:").unwrap();
+        for seq in 1..=5 {
+            store.save_message("room", &message(seq, &format!("m{}", seq))).unwrap();
+        }
+        store.save_message("other", &message(1, "other")).unwrap();
+
+        let mut edited = message(2, "edited");
+        edited.edited_at = Some(chrono::Utc::now());
+        store.update_message("room", &edited).unwrap();
+        store.delete_message("room", 4).unwrap();
+
+        assert_eq!(contents(&store.load_messages("room", None, 10).unwrap()), ["m1", "edited", "m3", "m5"]);
+        // 取 before 之前最新的 limit 条，按序号升序返回
+        assert_eq!(contents(&store.load_messages("room", None, 2).unwrap()), ["m3", "m5"]);
+        assert_eq!(contents(&store.load_messages("room", Some(3), 10).unwrap()), ["m1", "edited"]);
+        assert!(store.load_messages("room", Some(1), 10).unwrap().is_empty());
+    }
+
+    #[test]
+    fn sqlite_keeps_last_seq_after_deleting_newest_message() {
+        let store = SqliteRoomStore::open(":memory:").unwrap();
+        let room = ChatRoom::new("room".to_string(), None, OwnerToken::generate().0, true, 10);
+        store.save_room(&room).unwrap();
+        for seq in 1..=3 {
+            store.save_message(&room.id, &message(seq, "m")).unwrap();
+        }
+        store.delete_message(&room.id, 3).unwrap();
+        // 保存房间不会覆盖已记录的序号
+        store.save_room(&room).unwrap();
+
+        assert_eq!(store.load_sequences().unwrap(), [(room.id.clone(), 3)]);
+    }
+
+    #[test]
+    fn sqlite_adds_missing_columns_to_old_databases() {
+        let path = std::env::temp_dir().join(format!("chatroom-migrate-{}.db", uuid::Uuid::new_v4()));
+        let path = path.to_str().unwrap().to_string();
+        {
+            // 最早版本的表结构，没有 history_enabled、max_members 和 seq 列
+            let conn = Connection::open(&path).unwrap();
+            conn.execute_batch(
+                "CREATE TABLE rooms (
+                    id TEXT PRIMARY KEY,
+                    name TEXT NOT NULL,
+                    password_hash TEXT,
+                    owner_token_hash BLOB NOT NULL,
+                    created_at TEXT NOT NULL,
+                    last_active_at TEXT NOT NULL
+                );
+                CREATE TABLE messages (
+                    id INTEGER PRIMARY KEY AUTOINCREMENT,
+                    room_id TEXT NOT NULL,
+                    timestamp_us INTEGER NOT NULL,
+                    payload TEXT NOT NULL
+                );",
+            ).unwrap();
+            let now = chrono::Utc::now();
+            conn.execute(
+                "INSERT INTO rooms (id, name, password_hash, owner_token_hash, created_at, last_active_at) VALUES ('old', 'old room', NULL, x'00', ?1, ?1)",
+                params![now],
+            ).unwrap();
+        }
+
+        let store = SqliteRoomStore::open(&path).unwrap();
+        let rooms = store.load_rooms().unwrap();
+        assert_eq!(rooms.len(), 1);
+        assert!(!rooms[0].history_enabled);
+        assert_eq!(rooms[0].max_members, 0);
+        store.save_message("old", &message(1, "m1")).unwrap();
+        assert_eq!(contents(&store.load_messages("old", None, 10).unwrap()), ["m1"]);
+        assert_eq!(store.load_sequences().unwrap(), [("old".to_string(), 1)]);
+
+        // 再次打开时不会重复添加
+        drop(store);
+        assert!(SqliteRoomStore::open(&path).is_ok());
+        let _ = std::fs::remove_file(&path);
+    }
+
+    #[test]
+    fn writer_applies_writes_in_order() {
+        let store: Arc<dyn RoomStore> = Arc::new(SqliteRoomStore::open(":memory:").unwrap());
+        let writer = StoreWriter::spawn(store.clone()).unwrap();
+        let mut room = ChatRoom::new("room".to_string(), None, OwnerToken::generate().0, true, 10);
+        writer.save_room(&room);
+        room.name = "renamed".to_string();
+        writer.save_room(&room);
+        writer.save_message(&room.id, &message(1, "m1"));
+        writer.update_message(&room.id, &message(1, "edited"));
+        writer.save_message(&room.id, &message(2, "m2"));
+        writer.delete_message(&room.id, 2);
+        writer.flush().recv().unwrap();
+
+        assert_eq!(store.load_rooms().unwrap()[0].name, "renamed");
+        assert_eq!(contents(&store.load_messages(&room.id, None, 10).unwrap()), ["edited"]);
+
+        writer.delete_room(&room.id);
+        writer.flush().recv().unwrap();
+        assert!(store.load_rooms().unwrap().is_empty());
+        assert!(store.load_messages(&room.id, None, 10).unwrap().is_empty());
+    }
+}
@@ -0,0 +1,146 @@
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use base64::Engine;
+use hmac::{Hmac, Mac};
+use rand::RngCore;
+use serde::{Deserialize, Serialize};
+use sha2::Sha256;
+use crate::config::TicketConfig;
+use crate::error::ChatError;
+
+type HmacSha256 = Hmac<Sha256>;
+
+const TICKET_TTL_SECS: i64 = 60;
+
+// 通过 REST 校验后签发的入场券，WebSocket 加入时凭此证明已通过密码校验
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct JoinTicket {
+    pub room_id: String,
+    pub username: String,
+    pub expires_at: i64,
+}
+
+pub struct TicketSigner {
+    secret: Vec<u8>,
+}
+
+impl TicketSigner {
+    pub fn new(secret: Vec<u8>) -> Self {
+        Self { secret }
+    }
+
+    // 密钥未配置时随机生成（重启后旧入场券全部失效）
+    pub fn from_config(config: &TicketConfig) -> Self {
+        match &config.secret {
+            Some(secret) => Self::new(secret.trim().as_bytes().to_vec()),
+            None => {
+                log::warn!("入场券签名密钥未设置，使用随机生成的密钥");
+                let mut secret = vec![0u8; 32];
+                rand::thread_rng().fill_bytes(&mut secret);
+                Self::new(secret)
+            }
+        }
+    }
+
+    pub fn issue(&self, room_id: &str, username: &str) -> String {
+        self.sign(&JoinTicket {
+            room_id: room_id.to_string(),
+            username: username.to_string(),
+            expires_at: chrono::Utc::now().timestamp() + TICKET_TTL_SECS,
+        })
+    }
+
+    fn sign(&self, ticket: &JoinTicket) -> String {
+        let payload = URL_SAFE_NO_PAD.encode(serde_json::to_vec(ticket).unwrap_or_default());
+        let signature = URL_SAFE_NO_PAD.encode(self.mac(payload.as_bytes()).finalize().into_bytes());
+        format!("{}.{}", payload, signature)
+    }
+
+    pub fn verify(&self, token: &str) -> Result<JoinTicket, ChatError> {
+        let (payload, signature) = token.split_once('.')
+            .ok_or(ChatError::InvalidTicket)?;
+        let signature = URL_SAFE_NO_PAD.decode(signature)
+            .map_err(|_| ChatError::InvalidTicket)?;
+
+        self.mac(payload.as_bytes())
+            .verify_slice(&signature)
+            .map_err(|_| ChatError::InvalidTicket)?;
+
+        let payload = URL_SAFE_NO_PAD.decode(payload)
+            .map_err(|_| ChatError::InvalidTicket)?;
+        let ticket: JoinTicket = serde_json::from_slice(&payload)
+            .map_err(|_| ChatError::InvalidTicket)?;
+
+        if ticket.expires_at < chrono::Utc::now().timestamp() {
+            return Err(ChatError::TicketExpired);
+        }
+
+        Ok(ticket)
+    }
+
+    fn mac(&self, data: &[u8]) -> HmacSha256 {
+        let mut mac = HmacSha256::new_from_slice(&self.secret)
+            .expect("HMAC accepts keys of any length");
+        mac.update(data);
+        mac
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn signer(secret: &str) -> TicketSigner {
+        TicketSigner::new(secret.as_bytes().to_vec())
+    }
+
+    #[test]
+    fn verifies_issued_ticket() {
+        let ticket = signer("secret").verify(&signer("secret").issue("room", "alice")).unwrap();
+        assert_eq!(ticket.room_id, "room");
+        assert_eq!(ticket.username, "alice");
+    }
+
+    #[test]
+    fn rejects_tampered_payload() {
+        let signer = signer("secret");
+        let token = signer.issue("room", "alice");
+        let (_, signature) = token.split_once('.').unwrap();
+
+        let forged = JoinTicket {
+            room_id: "room".to_string(),
+            username: "bob".to_string(),
+            expires_at: chrono::Utc::now().timestamp() + TICKET_TTL_SECS,
+        };
+        let payload = URL_SAFE_NO_PAD.encode(serde_json::to_vec(&forged).unwrap());
+        let token = format!("{}.{}", payload, signature);
+        assert!(matches!(signer.verify(&token), Err(ChatError::InvalidTicket)));
+    }
+
+    #[test]
+    fn rejects_tampered_signature() {
+        let signer = signer("secret");
+        let token = signer.issue("room", "alice");
+        let (payload, signature) = token.split_once('.').unwrap();
+        let flipped = if signature.starts_with('A') { 'B' } else { 'A' };
+        let token = format!("{}.{}{}", payload, flipped, &signature[1..]);
+        assert!(matches!(signer.verify(&token), Err(ChatError::InvalidTicket)));
+        assert!(matches!(signer.verify("not-a-ticket"), Err(ChatError::InvalidTicket)));
+    }
+
+    #[test]
+    fn rejects_wrong_secret() {
+        let token = signer("secret").issue("room", "alice");
+        assert!(matches!(signer("other").verify(&token), Err(ChatError::InvalidTicket)));
+    }
+
+    #[test]
+    fn rejects_expired_ticket() {
+        let signer = signer("secret");
+        let token = signer.sign(&JoinTicket {
+            room_id: "room".to_string(),
+            username: "alice".to_string(),
+            expires_at: chrono::Utc::now().timestamp() - 1,
+        });
+        assert!(matches!(signer.verify(&token), Err(ChatError::TicketExpired)));
+    }
+}
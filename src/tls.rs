@@ -0,0 +1,139 @@
+use rustls::crypto::{ring, CryptoProvider};
+use rustls::pki_types::pem::PemObject;
+use rustls::pki_types::{CertificateDer, PrivateKeyDer};
+use rustls::server::{ClientHello, ResolvesServerCert};
+use rustls::sign::CertifiedKey;
+use rustls::ServerConfig;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, RwLock};
+use std::time::{Duration, SystemTime};
+use tokio::signal::unix::{signal, Signal, SignalKind};
+
+// 检查证书文件是否被替换的间隔
+const WATCH_INTERVAL: Duration = Duration::from_secs(10);
+
+// 每次握手时取当前证书，替换证书只影响之后建立的连接
+#[derive(Debug)]
+struct CertResolver {
+    current: RwLock<Arc<CertifiedKey>>,
+}
+
+impl ResolvesServerCert for CertResolver {
+    fn resolve(&self, _client_hello: ClientHello<'_>) -> Option<Arc<CertifiedKey>> {
+        self.current.read().ok().map(|key| key.clone())
+    }
+}
+
+// 持有证书路径，收到 SIGHUP 或证书文件变化时重新加载
+#[derive(Debug)]
+pub struct TlsReloader {
+    cert_path: PathBuf,
+    key_path: PathBuf,
+    provider: Arc<CryptoProvider>,
+    resolver: Arc<CertResolver>,
+}
+
+impl TlsReloader {
+    pub fn new(cert_path: &Path, key_path: &Path) -> Result<Self, String> {
+        let provider = Arc::new(ring::default_provider());
+        let key = load_certified_key(cert_path, key_path, &provider)?;
+        Ok(Self {
+            cert_path: cert_path.to_path_buf(),
+            key_path: key_path.to_path_buf(),
+            provider,
+            resolver: Arc::new(CertResolver { current: RwLock::new(Arc::new(key)) }),
+        })
+    }
+
+    pub fn server_config(&self) -> Result<ServerConfig, String> {
+        let builder = ServerConfig::builder_with_provider(self.provider.clone())
+            .with_safe_default_protocol_versions()
+            .map_err(|e| format!("初始化 TLS 失败: {}", e))?;
+        Ok(builder.with_no_client_auth().with_cert_resolver(self.resolver.clone()))
+    }
+
+    // 加载失败时继续使用旧证书
+    pub fn reload(&self) {
+        match load_certified_key(&self.cert_path, &self.key_path, &self.provider) {
+            Ok(key) => {
+                if let Ok(mut current) = self.resolver.current.write() {
+                    *current = Arc::new(key);
+                    log::info!("已重新加载 TLS 证书 {}", self.cert_path.display());
+                }
+            }
+            Err(e) => log::error!("重新加载 TLS 证书失败，继续使用旧证书: {}", e),
+        }
+    }
+
+    fn modified(&self) -> Option<(SystemTime, SystemTime)> {
+        let modified = |path: &Path| std::fs::metadata(path).and_then(|metadata| metadata.modified()).ok();
+        Some((modified(&self.cert_path)?, modified(&self.key_path)?))
+    }
+
+    pub async fn watch(self: Arc<Self>) {
+        let mut hangup = match signal(SignalKind::hangup()) {
+            Ok(hangup) => Some(hangup),
+            Err(e) => {
+                log::warn!("无法监听 SIGHUP，只在证书文件变化时重新加载: {}", e);
+                None
+            }
+        };
+        let mut interval = actix_web::rt::time::interval(WATCH_INTERVAL);
+        let mut last_modified = self.modified();
+
+        loop {
+            tokio::select! {
+                _ = interval.tick() => {
+                    // 证书和私钥可能先后写入，修改时间再次变化时会重新尝试
+                    let modified = self.modified();
+                    if modified.is_none() || modified == last_modified {
+                        continue;
+                    }
+                    last_modified = modified;
+                }
+                _ = recv_hangup(&mut hangup) => {
+                    log::info!("收到 SIGHUP，重新加载 TLS 证书");
+                }
+            }
+            self.reload();
+        }
+    }
+}
+
+// 未启用 TLS 时也要接管 SIGHUP，否则 systemctl reload 发送的信号会按默认行为直接终止进程，跳过优雅退出
+pub async fn ignore_hangup() {
+    let mut hangup = match signal(SignalKind::hangup()) {
+        Ok(hangup) => hangup,
+        Err(e) => {
+            log::warn!("无法监听 SIGHUP: {}", e);
+            return;
+        }
+    };
+    while hangup.recv().await.is_some() {
+        log::info!("收到 SIGHUP，未启用 TLS，无需重新加载；修改配置后请重启服务");
+    }
+}
+
+// 无法监听信号时永远不返回
+async fn recv_hangup(hangup: &mut Option<Signal>) {
+    if let Some(hangup) = hangup {
+        if hangup.recv().await.is_some() {
+            return;
+        }
+    }
+    std::future::pending().await
+}
+
+fn load_certified_key(cert_path: &Path, key_path: &Path, provider: &CryptoProvider) -> Result<CertifiedKey, String> {
+    let certs = CertificateDer::pem_file_iter(cert_path)
+        .and_then(|certs| certs.collect::<Result<Vec<_>, _>>())
+        .map_err(|e| format!("读取证书 {} 失败: {}", cert_path.display(), e))?;
+    if certs.is_empty() {
+        return Err(format!("证书文件 {} 中没有证书", cert_path.display()));
+    }
+    let key = PrivateKeyDer::from_pem_file(key_path)
+        .map_err(|e| format!("读取私钥 {} 失败: {}", key_path.display(), e))?;
+
+    CertifiedKey::from_der(certs, key, provider)
+        .map_err(|e| format!("证书与私钥不匹配或格式不受支持: {}", e))
+}
@@ -0,0 +1,177 @@
+use icu_normalizer::ComposingNormalizer;
+use icu_properties::props::{EmojiModifier, ExtendedPictographic, RegionalIndicator};
+use icu_properties::CodePointSetData;
+use std::fmt;
+use crate::config::LimitsConfig;
+
+// 消息内容和昵称的长度上限（按字符计），来自配置的 limits 部分
+#[derive(Debug, Clone, Copy)]
+pub struct ContentLimits {
+    pub max_content_chars: usize,
+    pub max_username_chars: usize,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum ValidationError {
+    ContentEmpty,
+    ContentTooLong(usize),
+    UsernameEmpty,
+    UsernameTooLong(usize),
+    InvalidCharacters,
+}
+
+impl ValidationError {
+    // 返回给客户端的错误代码，与 ChatError 的代码风格一致
+    pub fn code(&self) -> &'static str {
+        match self {
+            Self::ContentEmpty => "CONTENT_EMPTY",
+            Self::ContentTooLong(_) => "CONTENT_TOO_LONG",
+            Self::UsernameEmpty => "USERNAME_EMPTY",
+            Self::UsernameTooLong(_) => "USERNAME_TOO_LONG",
+            Self::InvalidCharacters => "INVALID_CHARACTERS",
+        }
+    }
+}
+
+impl fmt::Display for ValidationError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::ContentEmpty => write!(f, "消息内容不能为空"),
+            Self::ContentTooLong(max) => write!(f, "消息内容不能超过 {} 个字符", max),
+            Self::UsernameEmpty => write!(f, "昵称不能为空"),
+            Self::UsernameTooLong(max) => write!(f, "昵称不能超过 {} 个字符", max),
+            Self::InvalidCharacters => write!(f, "包含不允许的控制字符"),
+        }
+    }
+}
+
+impl ContentLimits {
+    pub fn new(config: &LimitsConfig) -> Self {
+        Self {
+            max_content_chars: config.max_message_length,
+            max_username_chars: config.max_username_length,
+        }
+    }
+
+    // NFC 规范化并去掉首尾空白，保留换行和制表符
+    pub fn normalize_content(&self, content: &str) -> Result<String, ValidationError> {
+        let content = normalize(content);
+        if content.chars().any(|c| c.is_control() && c != '\n' && c != '\t') {
+            return Err(ValidationError::InvalidCharacters);
+        }
+        if content.is_empty() {
+            return Err(ValidationError::ContentEmpty);
+        }
+        if content.chars().count() > self.max_content_chars {
+            return Err(ValidationError::ContentTooLong(self.max_content_chars));
+        }
+        Ok(content)
+    }
+
+    // 昵称还禁止零宽字符和双向文本控制符，避免伪装成其他用户
+    pub fn normalize_username(&self, username: &str) -> Result<String, ValidationError> {
+        let username = normalize(username);
+        if username.chars().any(|c| c.is_control() || is_invisible_format(c)) {
+            return Err(ValidationError::InvalidCharacters);
+        }
+        if username.is_empty() {
+            return Err(ValidationError::UsernameEmpty);
+        }
+        if username.chars().count() > self.max_username_chars {
+            return Err(ValidationError::UsernameTooLong(self.max_username_chars));
+        }
+        Ok(username)
+    }
+}
+
+// 表情回应必须是单个表情：国旗（两个区域指示符）、键帽，或由零宽连接符（U+200D）连接的表情字符序列，
+// 每个表情字符后可以跟变体选择符 U+FE0F、肤色修饰符或标签序列（如英格兰旗）；普通文字、引号等一律拒绝
+pub fn is_valid_emoji(emoji: &str) -> bool {
+    let chars: Vec<char> = emoji.chars().collect();
+    let regional_indicator = CodePointSetData::new::<RegionalIndicator>();
+    match chars.as_slice() {
+        [first, second] if regional_indicator.contains(*first) && regional_indicator.contains(*second) => true,
+        [base, '\u{FE0F}', '\u{20E3}'] | [base, '\u{20E3}'] => base.is_ascii_digit() || matches!(base, '#' | '*'),
+        _ => emoji.split('\u{200D}').all(is_emoji_element),
+    }
+}
+
+fn is_emoji_element(element: &str) -> bool {
+    let mut chars = element.chars();
+    let Some(base) = chars.next() else {
+        return false;
+    };
+    if !CodePointSetData::new::<ExtendedPictographic>().contains(base) {
+        return false;
+    }
+    match chars.collect::<Vec<char>>().as_slice() {
+        [] | ['\u{FE0F}'] => true,
+        [modifier] => CodePointSetData::new::<EmojiModifier>().contains(*modifier),
+        [tags @ .., '\u{E007F}'] => !tags.is_empty() && tags.iter().all(|tag| ('\u{E0020}'..='\u{E007E}').contains(tag)),
+        _ => false,
+    }
+}
+
+fn normalize(text: &str) -> String {
+    ComposingNormalizer::new_nfc().normalize(text.trim()).trim().to_string()
+}
+
+fn is_invisible_format(c: char) -> bool {
+    matches!(c, '\u{200B}'..='\u{200F}' | '\u{202A}'..='\u{202E}' | '\u{2060}'..='\u{2069}' | '\u{FEFF}')
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn limits() -> ContentLimits {
+        ContentLimits { max_content_chars: 5, max_username_chars: 3 }
+    }
+
+    #[test]
+    fn normalizes_to_nfc_and_trims() {
+        // e + 组合重音符 与 é 规范化后相同
+        assert_eq!(limits().normalize_content("  cafe\u{301} ").unwrap(), "caf\u{e9}");
+        assert_eq!(limits().normalize_username("e\u{301}").unwrap(), "\u{e9}");
+    }
+
+    #[test]
+    fn rejects_control_characters() {
+        assert_eq!(limits().normalize_content("a\u{7}b"), Err(ValidationError::InvalidCharacters));
+        assert_eq!(limits().normalize_content("a\nb\tc").unwrap(), "a\nb\tc");
+        assert_eq!(limits().normalize_username("a\nb"), Err(ValidationError::InvalidCharacters));
+    }
+
+    #[test]
+    fn rejects_invisible_characters_in_username() {
+        assert_eq!(limits().normalize_username("a\u{200B}b"), Err(ValidationError::InvalidCharacters));
+        assert_eq!(limits().normalize_username("a\u{202E}b"), Err(ValidationError::InvalidCharacters));
+    }
+
+    #[test]
+    fn limits_length_in_characters() {
+        assert_eq!(limits().normalize_content("你好世界啊").unwrap(), "你好世界啊");
+        assert_eq!(limits().normalize_content("你好世界啊!"), Err(ValidationError::ContentTooLong(5)));
+        assert_eq!(limits().normalize_username("abcd"), Err(ValidationError::UsernameTooLong(3)));
+        // 组合字符规范化后按一个字符计
+        assert_eq!(limits().normalize_username("ab\u{e9}").unwrap(), "ab\u{e9}");
+        assert_eq!(limits().normalize_username("abe\u{301}").unwrap(), "ab\u{e9}");
+    }
+
+    #[test]
+    fn rejects_empty_after_trim() {
+        assert_eq!(limits().normalize_content(" \n "), Err(ValidationError::ContentEmpty));
+        assert_eq!(limits().normalize_username("   "), Err(ValidationError::UsernameEmpty));
+    }
+
+    #[test]
+    fn validates_emoji() {
+        for emoji in ["👍", "❤\u{FE0F}", "©\u{FE0F}", "👍🏽", "👨\u{200D}👩\u{200D}👧", "🏳\u{FE0F}\u{200D}🌈", "🇨🇳", "#\u{FE0F}\u{20E3}", "7\u{20E3}",
+            "🏴\u{E0067}\u{E0062}\u{E0065}\u{E006E}\u{E0067}\u{E007F}"] {
+            assert!(is_valid_emoji(emoji), "{:?}", emoji);
+        }
+        for text in ["", "lol", "abc", "1", "\" onclick=x", "<b>", "👍 ", "👍\u{202E}", "a\u{7}", "\u{200D}", "👍\u{200D}", "🇨", "👍👍"] {
+            assert!(!is_valid_emoji(text), "{:?}", text);
+        }
+    }
+}
@@ -1,43 +1,200 @@
 use actix::prelude::*;
 use actix_web_actors::ws;
+use rand::Rng;
 use serde::{Deserialize, Serialize};
 use std::time::{Duration, Instant};
-use std::collections::HashMap;
+use std::collections::{HashMap, VecDeque};
 use std::sync::Arc;
-use tokio::sync::RwLock;
+use tokio::sync::{watch, RwLock};
+use tracing::field::{display, Empty};
 use uuid::Uuid;
+use crate::chatroom::ChatRoomManager;
+use crate::ticket::TicketSigner;
+use crate::ratelimit::{ConnectionLimiter, Throttle};
+use crate::validation::{self, ContentLimits};
+use crate::error::ChatError;
+use crate::config::{Config, WebSocketConfig};
+use crate::metrics::Metrics;
 
-const HEARTBEAT_INTERVAL: Duration = Duration::from_secs(5);
-const CLIENT_TIMEOUT: Duration = Duration::from_secs(10);
+// 断线后保留成员身份、允许恢复的时长
+const RESUME_GRACE: Duration = Duration::from_secs(30);
+// 每个房间为断线恢复缓存的消息条数
+const REPLAY_BUFFER_SIZE: usize = 200;
+// 同一会话两次广播正在输入、已读位置的最小间隔
+const TYPING_INTERVAL: Duration = Duration::from_secs(2);
+const READ_RECEIPT_INTERVAL: Duration = Duration::from_secs(1);
+// 单条消息最多的不同表情数和单个表情的最大长度
+const MAX_REACTIONS_PER_MESSAGE: usize = 20;
+const MAX_EMOJI_CHARS: usize = 16;
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 #[serde(tag = "type")]
 pub enum ClientMessage {
+    // 发送者名称由服务器使用加入时绑定的昵称，客户端传入的 username 会被忽略
     #[serde(rename = "chat")]
     Chat {
         content: String,
-        username: String,
+        // 回复的消息ID
+        #[serde(default)]
+        reply_to: Option<String>,
     },
     #[serde(rename = "join")]
     Join {
-        room_id: String,
-        username: String,
-        password: Option<String>,
+        ticket: String,
+    },
+    // 断线重连后恢复原来的身份，并补发 last_seq 之后的消息
+    #[serde(rename = "resume")]
+    Resume {
+        resume_token: String,
+        last_seq: u64,
+    },
+    #[serde(rename = "typing_start")]
+    TypingStart,
+    #[serde(rename = "typing_stop")]
+    TypingStop,
+    // 已读到房间内序号为 seq 的消息
+    #[serde(rename = "read_up_to")]
+    ReadUpTo {
+        seq: u64,
+    },
+    // 只能修改或删除自己发送的消息
+    #[serde(rename = "edit_message")]
+    EditMessage {
+        id: String,
+        content: String,
+    },
+    #[serde(rename = "delete_message")]
+    DeleteMessage {
+        id: String,
+    },
+    // 私信，只发给同一房间内的指定用户
+    #[serde(rename = "direct")]
+    Direct {
+        to_user_id: String,
+        content: String,
+    },
+    #[serde(rename = "react")]
+    React {
+        message_id: String,
+        emoji: String,
+    },
+    #[serde(rename = "unreact")]
+    Unreact {
+        message_id: String,
+        emoji: String,
     },
     #[serde(rename = "ping")]
     Ping,
 }
 
+impl ClientMessage {
+    // 产生新内容的消息，需要先加入房间
+    fn creates_content(&self) -> bool {
+        matches!(
+            self,
+            Self::Chat { .. } | Self::Direct { .. } | Self::EditMessage { .. } | Self::React { .. } | Self::Unreact { .. }
+        )
+    }
+
+    // 消耗聊天令牌的消息：产生新内容的消息，以及需要获取全局锁的加入和恢复；已读位置和正在输入另有按会话的间隔限制
+    fn is_rate_limited(&self) -> bool {
+        self.creates_content() || matches!(self, Self::Join { .. } | Self::Resume { .. })
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ChatMessage {
+    // 全局唯一的消息ID
+    #[serde(default)]
+    pub id: String,
+    // 房间内单调递增的序号，客户端据此发现丢失的消息
+    #[serde(default)]
+    pub seq: u64,
+    pub content: String,
+    pub username: String,
+    pub timestamp: chrono::DateTime<chrono::Utc>,
+    pub user_id: String,
+    // 最后一次编辑的时间，未编辑过为空
+    #[serde(default)]
+    pub edited_at: Option<chrono::DateTime<chrono::Utc>>,
+    // 回复的消息ID
+    #[serde(default)]
+    pub reply_to: Option<String>,
+    // 按表情聚合的回应，按首次回应的顺序排列
+    #[serde(default)]
+    pub reactions: Vec<Reaction>,
+}
+
+// 私信不分配房间序号，也不保存到历史
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DirectMessage {
+    pub id: String,
+    pub from_user_id: String,
+    pub from_username: String,
+    pub to_user_id: String,
+    pub content: String,
+    pub timestamp: chrono::DateTime<chrono::Utc>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Reaction {
+    pub emoji: String,
+    pub count: usize,
+    pub user_ids: Vec<String>,
+}
+
+impl ChatMessage {
+    // 同一用户对同一表情只计一次，返回回应是否有变化
+    pub fn react(&mut self, user_id: &str, emoji: &str) -> Result<bool, ChatError> {
+        let emoji = emoji.trim();
+        if emoji.is_empty() || emoji.chars().count() > MAX_EMOJI_CHARS || !validation::is_valid_emoji(emoji) {
+            return Err(ChatError::InvalidEmoji);
+        }
+
+        let distinct = self.reactions.len();
+        match self.reactions.iter_mut().find(|reaction| reaction.emoji == emoji) {
+            Some(reaction) if reaction.user_ids.iter().any(|id| id == user_id) => Ok(false),
+            Some(reaction) => {
+                reaction.user_ids.push(user_id.to_string());
+                reaction.count = reaction.user_ids.len();
+                Ok(true)
+            }
+            None if distinct >= MAX_REACTIONS_PER_MESSAGE => {
+                Err(ChatError::TooManyReactions)
+            }
+            None => {
+                self.reactions.push(Reaction {
+                    emoji: emoji.to_string(),
+                    count: 1,
+                    user_ids: vec![user_id.to_string()],
+                });
+                Ok(true)
+            }
+        }
+    }
+
+    pub fn unreact(&mut self, user_id: &str, emoji: &str) -> bool {
+        let emoji = emoji.trim();
+        let Some(reaction) = self.reactions.iter_mut().find(|reaction| reaction.emoji == emoji) else {
+            return false;
+        };
+        let before = reaction.user_ids.len();
+        reaction.user_ids.retain(|id| id != user_id);
+        reaction.count = reaction.user_ids.len();
+        let changed = reaction.count != before;
+        self.reactions.retain(|reaction| reaction.count > 0);
+        changed
+    }
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 #[serde(tag = "type")]
 pub enum ServerMessage {
     #[serde(rename = "chat")]
-    Chat {
-        content: String,
-        username: String,
-        timestamp: chrono::DateTime<chrono::Utc>,
-        user_id: String,
-    },
+    Chat(ChatMessage),
+    // 发给接收者，同时回显给发送者
+    #[serde(rename = "direct")]
+    Direct(DirectMessage),
     #[serde(rename = "user_joined")]
     UserJoined {
         username: String,
@@ -52,38 +209,327 @@ pub enum ServerMessage {
     },
     #[serde(rename = "error")]
     Error {
+        // 稳定的错误代码，见 ChatError::code；message 仅用于展示
+        code: String,
         message: String,
     },
     #[serde(rename = "joined")]
     Joined {
         room_id: String,
         user_id: String,
+        // 断线后用于 resume 的令牌
+        resume_token: String,
+        // 房间当前最后一条消息的序号
+        last_seq: u64,
+    },
+    // 加入或恢复后发送的当前成员列表
+    #[serde(rename = "members")]
+    Members {
+        members: Vec<MemberInfo>,
     },
     #[serde(rename = "pong")]
     Pong,
+    // 加入后发送的服务器端历史消息，按时间升序
+    #[serde(rename = "history")]
+    History {
+        messages: Vec<ChatMessage>,
+    },
+    #[serde(rename = "message_edited")]
+    MessageEdited {
+        id: String,
+        seq: u64,
+        content: String,
+        edited_at: chrono::DateTime<chrono::Utc>,
+    },
+    #[serde(rename = "message_deleted")]
+    MessageDeleted {
+        id: String,
+        seq: u64,
+    },
+    // 消息的全部表情回应
+    #[serde(rename = "reactions_updated")]
+    ReactionsUpdated {
+        message_id: String,
+        seq: u64,
+        reactions: Vec<Reaction>,
+    },
+    #[serde(rename = "room_renamed")]
+    RoomRenamed {
+        name: String,
+    },
+    #[serde(rename = "room_closed")]
+    RoomClosed {
+        room_id: String,
+    },
+    #[serde(rename = "kicked")]
+    Kicked {
+        room_id: String,
+    },
+    #[serde(rename = "ownership_granted")]
+    OwnershipGranted {
+        room_id: String,
+        owner_token: String,
+    },
+    // 服务器即将重启，随后以 1012 关闭连接，客户端等待 reconnect_after_ms 后重连
+    #[serde(rename = "server_shutdown")]
+    ServerShutdown {
+        reconnect_after_ms: u64,
+    },
+    // 以下为临时事件，只转发给当前在线的成员，不保存也不补发
+    #[serde(rename = "typing_start")]
+    TypingStart {
+        user_id: String,
+        username: String,
+    },
+    #[serde(rename = "typing_stop")]
+    TypingStop {
+        user_id: String,
+        username: String,
+    },
+    #[serde(rename = "read_up_to")]
+    ReadUpTo {
+        user_id: String,
+        username: String,
+        seq: u64,
+    },
+}
+
+// 房间成员，断线等待恢复期间仍保留
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct MemberInfo {
+    pub user_id: String,
+    pub username: String,
+    pub joined_at: chrono::DateTime<chrono::Utc>,
+}
+
+impl ServerMessage {
+    pub fn error(error: ChatError) -> Self {
+        ServerMessage::Error { code: error.code().to_string(), message: error.to_string() }
+    }
+}
+
+// 断线后等待恢复的会话
+pub struct DetachedSession {
+    pub room_id: String,
+    pub username: String,
+    pub since: Instant,
 }
 
-// WebSocket会话管理器
+// 消息被编辑、删除或回应有变化的事件，断线恢复时补发
+pub struct MessageChange {
+    pub message_id: String,
+    pub seq: u64,
+    pub event: ServerMessage,
+}
+
+// 恢复令牌对应的会话身份
+pub struct ResumeEntry {
+    pub user_id: String,
+    pub room_id: String,
+    pub username: String,
+}
+
+// WebSocket会话管理器，房间成员关系的唯一来源
 pub struct WebSocketManager {
     pub sessions: HashMap<String, Addr<WebSocketSession>>,
-    pub rooms: HashMap<String, Vec<String>>, // room_id -> user_ids
+    pub rooms: HashMap<String, Vec<String>>, // room_id -> user_ids，包含断线等待恢复的用户
+    pub members: HashMap<String, MemberInfo>, // user_id -> 加入时绑定的昵称和加入时间
+    pub sequences: HashMap<String, u64>, // room_id -> 最后分配的消息序号
+    pub replay: HashMap<String, VecDeque<ChatMessage>>, // room_id -> 最近的消息，用于断线恢复后补发
+    pub changes: HashMap<String, VecDeque<MessageChange>>, // room_id -> 最近的修改事件，同一条消息的同类事件只保留最新一条
+    pub resume_tokens: HashMap<String, ResumeEntry>, // resume_token -> 会话身份
+    pub detached: HashMap<String, DetachedSession>, // user_id -> 断线信息
+    pub metrics: Arc<Metrics>,
 }
 
 impl WebSocketManager {
-    pub fn new() -> Self {
+    pub fn new(metrics: Arc<Metrics>) -> Self {
         Self {
             sessions: HashMap::new(),
             rooms: HashMap::new(),
+            members: HashMap::new(),
+            sequences: HashMap::new(),
+            replay: HashMap::new(),
+            changes: HashMap::new(),
+            resume_tokens: HashMap::new(),
+            detached: HashMap::new(),
+            metrics,
+        }
+    }
+
+    // 从已保存的历史恢复序号，保证重启后序号继续递增
+    pub fn seed_sequence(&mut self, room_id: String, seq: u64) {
+        self.sequences.insert(room_id, seq);
+    }
+
+    pub fn last_seq(&self, room_id: &str) -> u64 {
+        self.sequences.get(room_id).copied().unwrap_or(0)
+    }
+
+    pub fn forget_room(&mut self, room_id: &str) {
+        self.sequences.remove(room_id);
+        self.replay.remove(room_id);
+        self.changes.remove(room_id);
+    }
+
+    // 分配消息ID和房间内序号后广播，返回带序号的消息
+    pub fn broadcast_chat(&mut self, room_id: &str, mut message: ChatMessage) -> ChatMessage {
+        let seq = self.sequences.entry(room_id.to_string()).or_insert(0);
+        *seq += 1;
+        message.seq = *seq;
+        message.id = Uuid::new_v4().to_string();
+
+        let replay = self.replay.entry(room_id.to_string()).or_default();
+        replay.push_back(message.clone());
+        while replay.len() > REPLAY_BUFFER_SIZE {
+            replay.pop_front();
+        }
+
+        self.metrics.chat_messages.inc();
+        self.broadcast_to_room(room_id, ServerMessage::Chat(message.clone()), None);
+        message
+    }
+
+    // 在恢复缓冲中查找消息
+    pub fn find_message(&self, room_id: &str, message_id: &str) -> Option<&ChatMessage> {
+        self.replay.get(room_id)?.iter().find(|message| message.id == message_id)
+    }
+
+    // 更新恢复缓冲中的消息，补发时使用修改后的内容
+    pub fn update_message(&mut self, room_id: &str, message: &ChatMessage) {
+        if let Some(cached) = self.replay.get_mut(room_id)
+            .and_then(|replay| replay.iter_mut().find(|cached| cached.id == message.id))
+        {
+            *cached = message.clone();
+        }
+    }
+
+    pub fn remove_message(&mut self, room_id: &str, message_id: &str) {
+        if let Some(replay) = self.replay.get_mut(room_id) {
+            replay.retain(|message| message.id != message_id);
+        }
+    }
+
+    // 缓存修改事件后广播；删除事件作为墓碑保留，取代该消息之前的编辑和回应事件
+    pub fn broadcast_change(&mut self, room_id: &str, message_id: &str, seq: u64, event: ServerMessage) {
+        let changes = self.changes.entry(room_id.to_string()).or_default();
+        let deleted = matches!(event, ServerMessage::MessageDeleted { .. });
+        changes.retain(|change| {
+            change.message_id != message_id
+                || !(deleted || std::mem::discriminant(&change.event) == std::mem::discriminant(&event))
+        });
+        changes.push_back(MessageChange { message_id: message_id.to_string(), seq, event: event.clone() });
+        while changes.len() > REPLAY_BUFFER_SIZE {
+            changes.pop_front();
         }
+
+        self.broadcast_to_room(room_id, event, None);
+    }
+
+    // 序号不超过 last_seq 的消息上的修改事件；更新的消息已随补发内容带上最新状态，无需重复
+    pub fn changes_until(&self, room_id: &str, last_seq: u64) -> Vec<ServerMessage> {
+        self.changes.get(room_id)
+            .map(|changes| changes.iter().filter(|change| change.seq <= last_seq).map(|change| change.event.clone()).collect())
+            .unwrap_or_default()
+    }
+
+    // 序号大于 last_seq 的缓冲消息
+    pub fn replay_since(&self, room_id: &str, last_seq: u64) -> Vec<ChatMessage> {
+        self.replay.get(room_id)
+            .map(|replay| replay.iter().filter(|message| message.seq > last_seq).cloned().collect())
+            .unwrap_or_default()
     }
 
-    pub fn add_session(&mut self, user_id: String, room_id: String, addr: Addr<WebSocketSession>) {
+    pub fn add_session(&mut self, user_id: String, room_id: String, username: String, addr: Addr<WebSocketSession>) {
         self.sessions.insert(user_id.clone(), addr);
-        self.rooms.entry(room_id).or_insert_with(Vec::new).push(user_id);
+        self.members.insert(user_id.clone(), MemberInfo {
+            user_id: user_id.clone(),
+            username,
+            joined_at: chrono::Utc::now(),
+        });
+        self.rooms.entry(room_id).or_default().push(user_id);
+    }
+
+    // 房间内昵称不区分大小写唯一，断线等待恢复的用户仍占用昵称
+    pub fn is_username_taken(&self, room_id: &str, username: &str) -> bool {
+        let username = username.trim().to_lowercase();
+        self.rooms.get(room_id).is_some_and(|users| {
+            users.iter().any(|user_id| {
+                self.members.get(user_id).is_some_and(|member| member.username.trim().to_lowercase() == username)
+            })
+        })
+    }
+
+    // 为会话签发新的恢复令牌，旧令牌作废
+    pub fn issue_resume_token(&mut self, user_id: &str, room_id: &str, username: &str) -> String {
+        self.resume_tokens.retain(|_, entry| entry.user_id != user_id);
+        let token = Uuid::new_v4().to_string();
+        self.resume_tokens.insert(token.clone(), ResumeEntry {
+            user_id: user_id.to_string(),
+            room_id: room_id.to_string(),
+            username: username.to_string(),
+        });
+        token
+    }
+
+    // 连接意外断开时保留成员身份等待恢复；会话已被新连接接管时返回 false
+    pub fn detach_session(&mut self, user_id: &str, room_id: &str, username: &str, addr: &Addr<WebSocketSession>) -> bool {
+        if self.sessions.get(user_id) != Some(addr) {
+            return false;
+        }
+        self.sessions.remove(user_id);
+        self.detached.insert(user_id.to_string(), DetachedSession {
+            room_id: room_id.to_string(),
+            username: username.to_string(),
+            since: Instant::now(),
+        });
+        true
+    }
+
+    // 用恢复令牌把新连接接回原来的身份，仍连着的旧连接会被关闭
+    pub fn resume_session(&mut self, resume_token: &str, addr: Addr<WebSocketSession>) -> Option<ResumeEntry> {
+        let entry = self.resume_tokens.remove(resume_token)?;
+        if !self.rooms.get(&entry.room_id).is_some_and(|users| users.contains(&entry.user_id)) {
+            return None;
+        }
+
+        self.detached.remove(&entry.user_id);
+        if let Some(old) = self.sessions.insert(entry.user_id.clone(), addr.clone()) {
+            if old != addr {
+                old.do_send(Disconnect {
+                    message: ServerMessage::error(ChatError::SessionReplaced),
+                });
+            }
+        }
+        Some(entry)
+    }
+
+    // 断线超过宽限期仍未恢复时移除成员，返回是否已移除
+    pub fn expire_detached(&mut self, user_id: &str) -> bool {
+        match self.detached.get(user_id) {
+            Some(detached) if detached.since.elapsed() >= RESUME_GRACE => {
+                let room_id = detached.room_id.clone();
+                self.remove_member(user_id, &room_id);
+                true
+            }
+            _ => false,
+        }
+    }
+
+    // 用户主动离开或被移出；会话已被新连接接管时返回 false
+    pub fn leave_session(&mut self, user_id: &str, room_id: &str, addr: &Addr<WebSocketSession>) -> bool {
+        if self.sessions.get(user_id).is_some_and(|current| current != addr) {
+            return false;
+        }
+        self.remove_member(user_id, room_id);
+        true
     }
 
-    pub fn remove_session(&mut self, user_id: &str, room_id: &str) {
+    fn remove_member(&mut self, user_id: &str, room_id: &str) {
         self.sessions.remove(user_id);
+        self.members.remove(user_id);
+        self.detached.remove(user_id);
+        self.resume_tokens.retain(|_, entry| entry.user_id != user_id);
         if let Some(users) = self.rooms.get_mut(room_id) {
             users.retain(|id| id != user_id);
             if users.is_empty() {
@@ -92,8 +538,85 @@ impl WebSocketManager {
         }
     }
 
+    // 房间成员列表，按加入顺序
+    pub fn members(&self, room_id: &str) -> Vec<MemberInfo> {
+        self.rooms.get(room_id)
+            .map(|users| users.iter().filter_map(|user_id| self.members.get(user_id).cloned()).collect())
+            .unwrap_or_default()
+    }
+
+    pub fn user_count(&self, room_id: &str) -> usize {
+        self.rooms.get(room_id).map_or(0, |users| users.len())
+    }
+
+    // 在线（未断线）的房间成员
+    pub fn is_member(&self, room_id: &str, user_id: &str) -> bool {
+        self.sessions.contains_key(user_id)
+            && self.rooms.get(room_id).is_some_and(|users| users.iter().any(|id| id == user_id))
+    }
+
+    pub fn send_to_user(&self, room_id: &str, user_id: &str, message: ServerMessage) -> bool {
+        if !self.is_member(room_id, user_id) {
+            return false;
+        }
+        match self.sessions.get(user_id) {
+            Some(addr) => {
+                addr.do_send(BroadcastMessage {
+                    room_id: room_id.to_string(),
+                    message,
+                    exclude_user: None,
+                    sent_at: Instant::now(),
+                });
+                true
+            }
+            None => false,
+        }
+    }
+
+    // 通知后断开单个用户，离开消息由会话停止时广播；断线等待恢复的用户直接移除
+    pub fn disconnect_user(&mut self, room_id: &str, user_id: &str, message: ServerMessage) -> bool {
+        if let Some(addr) = self.sessions.get(user_id) {
+            if !self.is_member(room_id, user_id) {
+                return false;
+            }
+            addr.do_send(Disconnect { message });
+            return true;
+        }
+
+        match self.detached.get(user_id) {
+            Some(detached) if detached.room_id == room_id => {
+                let username = detached.username.clone();
+                self.remove_member(user_id, room_id);
+                self.broadcast_to_room(room_id, ServerMessage::UserLeft {
+                    username,
+                    user_id: user_id.to_string(),
+                    timestamp: chrono::Utc::now(),
+                }, None);
+                true
+            }
+            _ => false,
+        }
+    }
+
+    // 先移出房间再断开，房间内不会再收到离开广播
+    pub fn close_room(&mut self, room_id: &str, message: ServerMessage) {
+        self.forget_room(room_id);
+        self.resume_tokens.retain(|_, entry| entry.room_id != room_id);
+        self.detached.retain(|_, detached| detached.room_id != room_id);
+        if let Some(user_ids) = self.rooms.remove(room_id) {
+            for user_id in user_ids {
+                self.members.remove(&user_id);
+                if let Some(addr) = self.sessions.remove(&user_id) {
+                    addr.do_send(Disconnect { message: message.clone() });
+                }
+            }
+        }
+    }
+
     pub fn broadcast_to_room(&self, room_id: &str, message: ServerMessage, exclude_user: Option<&str>) {
         if let Some(user_ids) = self.rooms.get(room_id) {
+            self.metrics.broadcasts.inc();
+            let sent_at = Instant::now();
             for user_id in user_ids {
                 if let Some(exclude_id) = exclude_user {
                     if user_id == exclude_id {
@@ -101,10 +624,11 @@ impl WebSocketManager {
                     }
                 }
                 if let Some(addr) = self.sessions.get(user_id) {
-                    let _ = addr.do_send(BroadcastMessage {
+                    addr.do_send(BroadcastMessage {
                         room_id: room_id.to_string(),
                         message: message.clone(),
                         exclude_user: None,
+                        sent_at,
                     });
                 }
             }
@@ -112,29 +636,120 @@ impl WebSocketManager {
     }
 }
 
+enum MessageAction {
+    Edit(String),
+    Delete,
+    React(String),
+    Unreact(String),
+}
+
 pub struct WebSocketSession {
     pub id: String,
     pub room_id: Option<String>,
     pub username: Option<String>,
+    // 所在房间是否开启服务器端历史，未开启时收发消息不需要 ChatRoomManager 的锁
+    pub history_enabled: bool,
+    // 主动离开或被断开时为 true，此时不保留恢复资格
+    pub leaving: bool,
     pub hb: Instant,
+    // 正在输入状态和最近一次广播时间
+    pub typing: bool,
+    pub last_typing: Option<Instant>,
+    // 已广播的已读位置；限流期间收到的更新先暂存，到期后再广播
+    pub read_seq: u64,
+    pub pending_read_seq: Option<u64>,
+    pub last_read_receipt: Option<Instant>,
+    pub chat_manager: Arc<RwLock<ChatRoomManager>>,
     pub ws_manager: Arc<RwLock<WebSocketManager>>,
+    pub ticket_signer: Arc<TicketSigner>,
+    pub limiter: ConnectionLimiter,
+    pub limits: ContentLimits,
+    pub config: WebSocketConfig,
+    pub metrics: Arc<Metrics>,
+    // 服务器开始排空时收到通知，等待期间由 started 中启动的任务持有
+    pub shutdown: Option<watch::Receiver<bool>>,
+    // 会话内的日志都挂在这个 span 下，带有会话ID、房间ID和客户端地址
+    pub span: tracing::Span,
+    pub log_content: bool,
 }
 
 impl WebSocketSession {
-    pub fn new(ws_manager: Arc<RwLock<WebSocketManager>>) -> Self {
+    #[allow(clippy::too_many_arguments)]
+    pub fn new(
+        chat_manager: Arc<RwLock<ChatRoomManager>>,
+        ws_manager: Arc<RwLock<WebSocketManager>>,
+        ticket_signer: Arc<TicketSigner>,
+        limiter: ConnectionLimiter,
+        config: &Config,
+        metrics: Arc<Metrics>,
+        shutdown: watch::Receiver<bool>,
+        request_id: Option<String>,
+    ) -> Self {
+        let id = Uuid::new_v4().to_string();
+        // ERROR 级别的 span 在任何日志级别下都不会被过滤，事件总能带上会话字段
+        let span = tracing::error_span!(
+            "ws_session",
+            session_id = %id,
+            request_id = Empty,
+            remote_addr = Empty,
+            user_id = Empty,
+            room_id = Empty,
+        );
+        if let Some(request_id) = &request_id {
+            span.record("request_id", display(request_id));
+        }
+        if let Some(ip) = limiter.ip() {
+            span.record("remote_addr", display(ip));
+        }
+
         Self {
-            id: Uuid::new_v4().to_string(),
+            id,
             room_id: None,
             username: None,
+            history_enabled: false,
+            leaving: false,
             hb: Instant::now(),
+            typing: false,
+            last_typing: None,
+            read_seq: 0,
+            pending_read_seq: None,
+            last_read_receipt: None,
+            chat_manager,
             ws_manager,
+            ticket_signer,
+            limiter,
+            limits: ContentLimits::new(&config.limits),
+            config: config.websocket,
+            metrics,
+            shutdown: Some(shutdown),
+            span,
+            log_content: config.log.message_content,
+        }
+    }
+
+    // 加入或恢复成功后记录身份，之后的日志都带有房间ID
+    fn record_identity(&self) {
+        self.span.record("user_id", display(&self.id));
+        if let Some(room_id) = &self.room_id {
+            self.span.record("room_id", display(room_id));
+        }
+    }
+
+    // 默认只记录长度，开启 message_content 后才记录内容
+    fn trace_content(&self, event: &str, content: &str) {
+        let length = content.chars().count();
+        if self.log_content {
+            tracing::debug!(parent: &self.span, length, content, "{}", event);
+        } else {
+            tracing::debug!(parent: &self.span, length, "{}", event);
         }
     }
 
     fn hb(&self, ctx: &mut <Self as Actor>::Context) {
-        ctx.run_interval(HEARTBEAT_INTERVAL, |act, ctx| {
-            if Instant::now().duration_since(act.hb) > CLIENT_TIMEOUT {
-                println!("WebSocket Client heartbeat failed, disconnecting!");
+        ctx.run_interval(self.config.heartbeat_interval(), |act, ctx| {
+            if Instant::now().duration_since(act.hb) > act.config.client_timeout() {
+                tracing::warn!(parent: &act.span, "心跳超时，断开连接");
+                act.metrics.heartbeat_timeouts.inc();
                 ctx.stop();
                 return;
             }
@@ -143,7 +758,266 @@ impl WebSocketSession {
         });
     }
 
+    // 服务器开始排空时通知客户端并关闭连接；已经在排空时立即关闭
+    fn watch_shutdown(&mut self, ctx: &mut <Self as Actor>::Context) {
+        let Some(mut shutdown) = self.shutdown.take() else {
+            return;
+        };
+        let wait = async move {
+            let draining = shutdown.wait_for(|draining| *draining).await.is_ok();
+            (shutdown, draining)
+        };
+        ctx.spawn(wait.into_actor(self).map(|(shutdown, draining), act, ctx| {
+            // 接收端随会话一起释放，排空时据此判断会话是否都已关闭
+            act.shutdown = Some(shutdown);
+            if draining {
+                act.shut_down(ctx);
+            }
+        }));
+    }
+
+    // 随机抖动避免所有客户端同时重连
+    fn shut_down(&mut self, ctx: &mut <Self as Actor>::Context) {
+        let base = self.config.reconnect_after_ms;
+        let reconnect_after_ms = rand::thread_rng().gen_range(base..=base.saturating_mul(2));
+        tracing::debug!(parent: &self.span, reconnect_after_ms, "服务器即将退出，通知客户端重连");
+        self.send_message(ServerMessage::ServerShutdown { reconnect_after_ms }, ctx);
+        ctx.close(Some(ws::CloseCode::Restart.into()));
+        ctx.stop();
+    }
+
+    // 转发临时事件给房间内的其他在线成员
+    fn broadcast_ephemeral(&self, message: ServerMessage) {
+        if let Some(room_id) = &self.room_id {
+            let ws_manager = self.ws_manager.clone();
+            let room_id = room_id.clone();
+            let user_id = self.id.clone();
+            actix::spawn(async move {
+                ws_manager.read().await.broadcast_to_room(&room_id, message, Some(&user_id));
+            });
+        }
+    }
+
+    fn set_typing(&mut self, typing: bool) {
+        let Some(username) = self.username.clone() else {
+            return;
+        };
+
+        if typing {
+            // 持续输入时客户端会重复发送，间隔内的重复通知直接丢弃
+            if self.last_typing.is_some_and(|last| last.elapsed() < TYPING_INTERVAL) {
+                return;
+            }
+            self.typing = true;
+            self.last_typing = Some(Instant::now());
+            self.broadcast_ephemeral(ServerMessage::TypingStart { user_id: self.id.clone(), username });
+        } else if self.typing {
+            self.typing = false;
+            self.last_typing = None;
+            self.broadcast_ephemeral(ServerMessage::TypingStop { user_id: self.id.clone(), username });
+        }
+    }
+
+    fn read_up_to(&mut self, seq: u64, ctx: &mut <Self as Actor>::Context) {
+        if seq <= self.read_seq || self.pending_read_seq.is_some_and(|pending| seq <= pending) {
+            return;
+        }
+
+        let wait = self.last_read_receipt
+            .map(|last| READ_RECEIPT_INTERVAL.saturating_sub(last.elapsed()))
+            .unwrap_or_default();
+        if wait.is_zero() {
+            self.flush_read_receipt(seq);
+            return;
+        }
+
+        // 限流期间只保留最大的已读位置，到期后广播
+        if self.pending_read_seq.replace(seq).is_none() {
+            ctx.run_later(wait, |act, _| {
+                if let Some(seq) = act.pending_read_seq.take() {
+                    act.flush_read_receipt(seq);
+                }
+            });
+        }
+    }
+
+    fn flush_read_receipt(&mut self, seq: u64) {
+        let Some(username) = self.username.clone() else {
+            return;
+        };
+        self.read_seq = seq;
+        self.last_read_receipt = Some(Instant::now());
+        self.broadcast_ephemeral(ServerMessage::ReadUpTo { user_id: self.id.clone(), username, seq });
+    }
+
+    fn send_direct(&self, to_user_id: String, content: String, ctx: &mut <Self as Actor>::Context) {
+        let (Some(room_id), Some(username)) = (self.room_id.clone(), self.username.clone()) else {
+            return;
+        };
+        if to_user_id == self.id {
+            self.send_message(ServerMessage::error(ChatError::DirectToSelf), ctx);
+            return;
+        }
+        let content = match self.limits.normalize_content(&content) {
+            Ok(content) => content,
+            Err(e) => {
+                self.send_message(ServerMessage::error(e.into()), ctx);
+                return;
+            }
+        };
+
+        self.trace_content("发送私信", &content);
+        let message = DirectMessage {
+            id: Uuid::new_v4().to_string(),
+            from_user_id: self.id.clone(),
+            from_username: username,
+            to_user_id,
+            content,
+            timestamp: chrono::Utc::now(),
+        };
+        let ws_manager = self.ws_manager.clone();
+
+        let send = async move {
+            let manager = ws_manager.read().await;
+            if manager.send_to_user(&room_id, &message.to_user_id, ServerMessage::Direct(message.clone())) {
+                Ok(message)
+            } else {
+                Err(ChatError::UserOffline)
+            }
+        };
+
+        ctx.wait(send.into_actor(self).map(|result, act, ctx| {
+            match result {
+                Ok(message) => act.send_message(ServerMessage::Direct(message), ctx),
+                Err(e) => act.send_message(ServerMessage::error(e), ctx),
+            }
+        }));
+    }
+
+    // 只能操作恢复缓冲或服务器端历史中仍保留的消息，编辑和删除仅限发送者本人
+    fn modify_message(&self, message_id: String, action: MessageAction, ctx: &mut <Self as Actor>::Context) {
+        let Some(room_id) = self.room_id.clone() else {
+            return;
+        };
+        let action = match action {
+            MessageAction::Edit(content) => match self.limits.normalize_content(&content) {
+                Ok(content) => {
+                    self.trace_content("编辑消息", &content);
+                    MessageAction::Edit(content)
+                }
+                Err(e) => {
+                    self.send_message(ServerMessage::error(e.into()), ctx);
+                    return;
+                }
+            },
+            action => action,
+        };
+        let chat_manager = self.chat_manager.clone();
+        let ws_manager = self.ws_manager.clone();
+        let user_id = self.id.clone();
+        let history_enabled = self.history_enabled;
+
+        let edit = async move {
+            // 与发送消息相同，只有开启历史的房间才需要 ChatRoomManager 的锁
+            let mut chat_manager = if history_enabled {
+                Some(chat_manager.write().await)
+            } else {
+                None
+            };
+            let mut manager = ws_manager.write().await;
+            let mut message = manager.find_message(&room_id, &message_id)
+                .or_else(|| chat_manager.as_ref()?.find_message(&room_id, &message_id))
+                .cloned()
+                .ok_or(ChatError::MessageNotFound)?;
+            if matches!(action, MessageAction::Edit(_) | MessageAction::Delete) && message.user_id != user_id {
+                return Err(ChatError::NotMessageSender);
+            }
+
+            let reacted = match &action {
+                MessageAction::React(emoji) => Some(message.react(&user_id, emoji)?),
+                MessageAction::Unreact(emoji) => Some(message.unreact(&user_id, emoji)),
+                _ => None,
+            };
+            if reacted == Some(false) {
+                return Ok(());
+            }
+
+            let (message_id, seq) = (message.id.clone(), message.seq);
+            let event = match action {
+                MessageAction::Edit(content) => {
+                    let edited_at = chrono::Utc::now();
+                    message.content = content.clone();
+                    message.edited_at = Some(edited_at);
+                    manager.update_message(&room_id, &message);
+                    if let Some(chat_manager) = chat_manager.as_mut() {
+                        chat_manager.update_message(&room_id, &message);
+                    }
+                    ServerMessage::MessageEdited { id: message.id, seq: message.seq, content, edited_at }
+                }
+                MessageAction::Delete => {
+                    manager.remove_message(&room_id, &message.id);
+                    if let Some(chat_manager) = chat_manager.as_mut() {
+                        chat_manager.remove_message(&room_id, &message);
+                    }
+                    ServerMessage::MessageDeleted { id: message.id, seq: message.seq }
+                }
+                MessageAction::React(_) | MessageAction::Unreact(_) => {
+                    manager.update_message(&room_id, &message);
+                    if let Some(chat_manager) = chat_manager.as_mut() {
+                        chat_manager.update_message(&room_id, &message);
+                    }
+                    ServerMessage::ReactionsUpdated { message_id: message.id, seq: message.seq, reactions: message.reactions }
+                }
+            };
+            manager.broadcast_change(&room_id, &message_id, seq, event);
+            Ok(())
+        };
+
+        ctx.wait(edit.into_actor(self).map(|result, act, ctx| {
+            if let Err(e) = result {
+                act.send_message(ServerMessage::error(e), ctx);
+            }
+        }));
+    }
+
+    // 消耗一个聊天令牌，超出速率时丢弃消息，违规过多时断开连接；返回是否继续处理
+    fn throttle(&mut self, ctx: &mut <Self as Actor>::Context) -> bool {
+        match self.limiter.check() {
+            Throttle::Allowed => true,
+            Throttle::Limited => {
+                tracing::debug!(parent: &self.span, "消息过于频繁，已丢弃");
+                self.send_message(ServerMessage::error(ChatError::RateLimited), ctx);
+                false
+            }
+            Throttle::Exceeded => {
+                self.disconnect_abusive(ctx);
+                false
+            }
+        }
+    }
+
+    // 加入或恢复失败时回复错误，并计为一次违规，避免同一连接反复尝试入场券或恢复令牌
+    fn reject_join(&mut self, error: ChatError, ctx: &mut <Self as Actor>::Context) {
+        self.metrics.join_failed("websocket", &error);
+        self.send_message(ServerMessage::error(error), ctx);
+        if let Throttle::Exceeded = self.limiter.violation() {
+            self.disconnect_abusive(ctx);
+        }
+    }
+
+    fn disconnect_abusive(&mut self, ctx: &mut <Self as Actor>::Context) {
+        tracing::warn!(parent: &self.span, "超出限流次数，断开连接");
+        self.leaving = true;
+        self.send_message(ServerMessage::error(ChatError::RateLimitExceeded), ctx);
+        ctx.close(Some(ws::CloseCode::Policy.into()));
+        ctx.stop();
+    }
+
     fn send_message(&self, msg: ServerMessage, ctx: &mut <Self as Actor>::Context) {
+        // 关闭帧之后不能再发送数据帧
+        if ctx.state() != ActorState::Running {
+            return;
+        }
         if let Ok(text) = serde_json::to_string(&msg) {
             ctx.text(text);
         }
@@ -154,20 +1028,47 @@ impl Actor for WebSocketSession {
     type Context = ws::WebsocketContext<Self>;
 
     fn started(&mut self, ctx: &mut Self::Context) {
+        self.metrics.websocket_sessions.inc();
+        tracing::debug!(parent: &self.span, "WebSocket 连接已建立");
         self.hb(ctx);
+        self.watch_shutdown(ctx);
     }
 
-    fn stopped(&mut self, _ctx: &mut Self::Context) {
+    fn stopped(&mut self, ctx: &mut Self::Context) {
+        self.metrics.websocket_sessions.dec();
+        tracing::info!(parent: &self.span, leaving = self.leaving, "WebSocket 连接已关闭");
+
         // 用户离开时清理会话
         if let (Some(room_id), Some(username)) = (&self.room_id, &self.username) {
+            let chat_manager = self.chat_manager.clone();
             let ws_manager = self.ws_manager.clone();
             let user_id = self.id.clone();
             let room_id_clone = room_id.clone();
             let username_clone = username.clone();
+            let addr = ctx.address();
+            let leaving = self.leaving;
             
             actix::spawn(async move {
+                if !leaving {
+                    // 意外断线先保留身份，宽限期内恢复则不广播离开
+                    if !ws_manager.write().await.detach_session(&user_id, &room_id_clone, &username_clone, &addr) {
+                        return;
+                    }
+                    actix_web::rt::time::sleep(RESUME_GRACE).await;
+                }
+
+                // 先刷新活跃时间再移除会话，回收任务不会误删刚变空的房间
+                chat_manager.write().await.touch_room(&room_id_clone);
+
                 let mut manager = ws_manager.write().await;
-                manager.remove_session(&user_id, &room_id_clone);
+                let removed = if leaving {
+                    manager.leave_session(&user_id, &room_id_clone, &addr)
+                } else {
+                    manager.expire_detached(&user_id)
+                };
+                if !removed {
+                    return;
+                }
                 
                 // 通知其他用户有用户离开
                 let leave_msg = ServerMessage::UserLeft {
@@ -193,60 +1094,235 @@ impl StreamHandler<Result<ws::Message, ws::ProtocolError>> for WebSocketSession
             }
             Ok(ws::Message::Text(text)) => {
                 self.hb = Instant::now();
-                
+
                 match serde_json::from_str::<ClientMessage>(&text) {
                     Ok(client_msg) => {
+                        if client_msg.is_rate_limited() && !self.throttle(ctx) {
+                            return;
+                        }
+                        if client_msg.creates_content() {
+                            // 例如服务器重启后恢复失败，客户端需要重新加入
+                            if self.room_id.is_none() {
+                                self.send_message(ServerMessage::error(ChatError::NotJoined), ctx);
+                                return;
+                            }
+                        }
                         match client_msg {
-                            ClientMessage::Chat { content, username } => {
-                                if let Some(room_id) = &self.room_id {
-                                    let server_msg = ServerMessage::Chat {
+                            ClientMessage::Chat { content, reply_to } => {
+                                // 发出消息即视为停止输入，客户端收到聊天消息时自行清除提示
+                                self.typing = false;
+                                self.last_typing = None;
+                                if let (Some(room_id), Some(username)) = (&self.room_id, &self.username) {
+                                    let content = match self.limits.normalize_content(&content) {
+                                        Ok(content) => content,
+                                        Err(e) => {
+                                            self.send_message(ServerMessage::error(e.into()), ctx);
+                                            return;
+                                        }
+                                    };
+                                    self.trace_content("发送聊天消息", &content);
+                                    let chat_msg = ChatMessage {
+                                        id: String::new(),
+                                        seq: 0,
                                         content,
-                                        username,
+                                        username: username.clone(),
                                         timestamp: chrono::Utc::now(),
                                         user_id: self.id.clone(),
+                                        edited_at: None,
+                                        reply_to,
+                                        reactions: Vec::new(),
                                     };
                                     
-                                    // 广播消息到房间内的所有用户
+                                    // 广播消息到房间内的所有用户，开启历史的房间同时记录
+                                    let chat_manager = self.chat_manager.clone();
                                     let ws_manager = self.ws_manager.clone();
                                     let room_id_clone = room_id.clone();
-                                    let msg_clone = server_msg.clone();
+                                    let history_enabled = self.history_enabled;
                                     
-                                    actix::spawn(async move {
-                                        let manager = ws_manager.read().await;
-                                        manager.broadcast_to_room(&room_id_clone, msg_clone, None);
-                                    });
+                                    let send = async move {
+                                        // 未开启历史的房间只需要分配序号和写入恢复缓冲，不占用 ChatRoomManager 的锁；
+                                        // 写入存储由后台线程完成，不在持锁期间等待
+                                        let mut chat_manager = if history_enabled {
+                                            Some(chat_manager.write().await)
+                                        } else {
+                                            None
+                                        };
+                                        let mut manager = ws_manager.write().await;
+                                        // 只能回复服务器仍缓存的同一房间内的消息
+                                        if let Some(reply_to) = &chat_msg.reply_to {
+                                            let found = manager.find_message(&room_id_clone, reply_to).is_some()
+                                                || chat_manager.as_ref().is_some_and(|chat_manager| {
+                                                    chat_manager.find_message(&room_id_clone, reply_to).is_some()
+                                                });
+                                            if !found {
+                                                return Err(ChatError::ReplyNotFound);
+                                            }
+                                        }
+                                        let chat_msg = manager.broadcast_chat(&room_id_clone, chat_msg);
+                                        if let Some(chat_manager) = chat_manager.as_mut() {
+                                            chat_manager.record_message(&room_id_clone, chat_msg);
+                                        }
+                                        Ok(())
+                                    };
+
+                                    // 按收到的顺序依次处理同一连接的消息
+                                    ctx.wait(send.into_actor(self).map(|result, act, ctx| {
+                                        if let Err(e) = result {
+                                            act.send_message(ServerMessage::error(e), ctx);
+                                        }
+                                    }));
                                 }
                             }
-                            ClientMessage::Join { room_id, username, password } => {
-                                self.room_id = Some(room_id.clone());
-                                self.username = Some(username.clone());
-                                
-                                // 将会话添加到WebSocket管理器
+                            ClientMessage::Join { ticket } => {
+                                if self.room_id.is_some() {
+                                    self.reject_join(ChatError::AlreadyJoined, ctx);
+                                    return;
+                                }
+
+                                // 入场券由 POST /api/rooms/join 在密码校验通过后签发
+                                let (room_id, username) = match self.ticket_signer.verify(&ticket) {
+                                    Ok(ticket) => (ticket.room_id, ticket.username),
+                                    Err(e) => {
+                                        self.reject_join(e, ctx);
+                                        return;
+                                    }
+                                };
+
+                                // 确认房间仍然存在，再登记到WebSocket管理器
+                                let chat_manager = self.chat_manager.clone();
                                 let ws_manager = self.ws_manager.clone();
                                 let user_id = self.id.clone();
                                 let room_id_clone = room_id.clone();
                                 let username_clone = username.clone();
                                 let addr = ctx.address();
-                                
-                                actix::spawn(async move {
+
+                                let join = async move {
+                                    // 持有读锁直到登记完成，避免房间在此期间被删除或回收
+                                    let chat_manager = chat_manager.read().await;
+                                    let room = chat_manager.get_room(&room_id_clone)
+                                        .ok_or(ChatError::RoomNotFound)?;
+
                                     let mut manager = ws_manager.write().await;
-                                    manager.add_session(user_id.clone(), room_id_clone.clone(), addr);
-                                    
+                                    if manager.user_count(&room_id_clone) >= room.max_members {
+                                        return Err(ChatError::RoomFull);
+                                    }
+                                    if manager.is_username_taken(&room_id_clone, &username_clone) {
+                                        return Err(ChatError::UsernameTaken);
+                                    }
+                                    manager.add_session(user_id.clone(), room_id_clone.clone(), username_clone.clone(), addr);
+
                                     // 通知其他用户有新用户加入
                                     let join_msg = ServerMessage::UserJoined {
-                                        username: username_clone,
+                                        username: username_clone.clone(),
                                         user_id: user_id.clone(),
                                         timestamp: chrono::Utc::now(),
                                     };
                                     manager.broadcast_to_room(&room_id_clone, join_msg, Some(&user_id));
-                                });
-                                
-                                let server_msg = ServerMessage::Joined {
-                                    room_id,
-                                    user_id: self.id.clone(),
+
+                                    let resume_token = manager.issue_resume_token(&user_id, &room_id_clone, &username_clone);
+                                    let last_seq = manager.last_seq(&room_id_clone);
+                                    let members = manager.members(&room_id_clone);
+                                    Ok::<_, ChatError>((resume_token, last_seq, members, room.history_enabled, chat_manager.recent_messages(&room_id_clone)))
                                 };
-                                self.send_message(server_msg, ctx);
+
+                                // 加入完成前暂停处理其他消息，避免漏掉房间内的广播
+                                ctx.wait(join.into_actor(self).map(move |result, act, ctx| {
+                                    let (resume_token, last_seq, members, history_enabled, history) = match result {
+                                        Ok(joined) => joined,
+                                        Err(e) => {
+                                            tracing::info!(parent: &act.span, code = e.code(), "加入聊天室失败");
+                                            act.reject_join(e, ctx);
+                                            return;
+                                        }
+                                    };
+
+                                    act.room_id = Some(room_id.clone());
+                                    act.username = Some(username);
+                                    act.history_enabled = history_enabled;
+                                    act.record_identity();
+                                    tracing::info!(parent: &act.span, "加入聊天室");
+
+                                    let server_msg = ServerMessage::Joined {
+                                        room_id,
+                                        user_id: act.id.clone(),
+                                        resume_token,
+                                        last_seq,
+                                    };
+                                    act.send_message(server_msg, ctx);
+                                    act.send_message(ServerMessage::Members { members }, ctx);
+
+                                    if let Some(messages) = history {
+                                        act.send_message(ServerMessage::History { messages }, ctx);
+                                    }
+                                }));
+                            }
+                            ClientMessage::Resume { resume_token, last_seq } => {
+                                if self.room_id.is_some() {
+                                    self.reject_join(ChatError::AlreadyJoined, ctx);
+                                    return;
+                                }
+
+                                let chat_manager = self.chat_manager.clone();
+                                let ws_manager = self.ws_manager.clone();
+                                let addr = ctx.address();
+
+                                let resume = async move {
+                                    let chat_manager = chat_manager.read().await;
+                                    let mut manager = ws_manager.write().await;
+                                    let entry = manager.resume_session(&resume_token, addr)
+                                        .ok_or(ChatError::SessionExpired)?;
+                                    let history_enabled = chat_manager.get_room(&entry.room_id)
+                                        .ok_or(ChatError::RoomNotFound)?
+                                        .history_enabled;
+
+                                    let resume_token = manager.issue_resume_token(&entry.user_id, &entry.room_id, &entry.username);
+                                    let missed = manager.replay_since(&entry.room_id, last_seq);
+                                    let changes = manager.changes_until(&entry.room_id, last_seq);
+                                    let last_seq = manager.last_seq(&entry.room_id);
+                                    let members = manager.members(&entry.room_id);
+                                    Ok::<_, ChatError>((entry, resume_token, last_seq, members, history_enabled, missed, changes))
+                                };
+
+                                // 与 join 相同，恢复完成前暂停处理其他消息
+                                ctx.wait(resume.into_actor(self).map(|result, act, ctx| {
+                                    let (entry, resume_token, last_seq, members, history_enabled, missed, changes) = match result {
+                                        Ok(resumed) => resumed,
+                                        Err(e) => {
+                                            tracing::info!(parent: &act.span, code = e.code(), "恢复会话失败");
+                                            act.reject_join(e, ctx);
+                                            return;
+                                        }
+                                    };
+
+                                    act.id = entry.user_id;
+                                    act.room_id = Some(entry.room_id.clone());
+                                    act.username = Some(entry.username);
+                                    act.history_enabled = history_enabled;
+                                    act.record_identity();
+                                    tracing::info!(parent: &act.span, missed = missed.len(), changes = changes.len(), "恢复会话");
+
+                                    act.send_message(ServerMessage::Joined {
+                                        room_id: entry.room_id,
+                                        user_id: act.id.clone(),
+                                        resume_token,
+                                        last_seq,
+                                    }, ctx);
+                                    act.send_message(ServerMessage::Members { members }, ctx);
+                                    act.send_message(ServerMessage::History { messages: missed }, ctx);
+                                    // 断线期间对已收到的消息所做的编辑、删除和回应
+                                    for change in changes {
+                                        act.send_message(change, ctx);
+                                    }
+                                }));
                             }
+                            ClientMessage::TypingStart => self.set_typing(true),
+                            ClientMessage::TypingStop => self.set_typing(false),
+                            ClientMessage::ReadUpTo { seq } => self.read_up_to(seq, ctx),
+                            ClientMessage::EditMessage { id, content } => self.modify_message(id, MessageAction::Edit(content), ctx),
+                            ClientMessage::DeleteMessage { id } => self.modify_message(id, MessageAction::Delete, ctx),
+                            ClientMessage::Direct { to_user_id, content } => self.send_direct(to_user_id, content, ctx),
+                            ClientMessage::React { message_id, emoji } => self.modify_message(message_id, MessageAction::React(emoji), ctx),
+                            ClientMessage::Unreact { message_id, emoji } => self.modify_message(message_id, MessageAction::Unreact(emoji), ctx),
                             ClientMessage::Ping => {
                                 let server_msg = ServerMessage::Pong;
                                 self.send_message(server_msg, ctx);
@@ -254,20 +1330,28 @@ impl StreamHandler<Result<ws::Message, ws::ProtocolError>> for WebSocketSession
                         }
                     }
                     Err(e) => {
-                        let error_msg = ServerMessage::Error {
-                            message: format!("Invalid message format: {}", e),
-                        };
+                        // 无法解析的消息同样计入限流，避免借此刷错误响应
+                        if !self.throttle(ctx) {
+                            return;
+                        }
+                        tracing::debug!(parent: &self.span, error = %e, "无法解析客户端消息");
+                        let error_msg = ServerMessage::error(ChatError::InvalidRequest(e.to_string()));
                         self.send_message(error_msg, ctx);
                     }
                 }
             }
-            Ok(ws::Message::Binary(_)) => {
-                println!("Unexpected binary message");
+            Ok(ws::Message::Binary(data)) => {
+                tracing::warn!(parent: &self.span, length = data.len(), "忽略二进制消息");
             }
             Ok(ws::Message::Close(reason)) => {
+                self.leaving = true;
                 ctx.close(reason);
                 ctx.stop();
             }
+            Err(e) => {
+                tracing::debug!(parent: &self.span, error = %e, "WebSocket 协议错误，断开连接");
+                ctx.stop();
+            }
             _ => ctx.stop(),
         }
     }
@@ -280,6 +1364,8 @@ pub struct BroadcastMessage {
     pub room_id: String,
     pub message: ServerMessage,
     pub exclude_user: Option<String>,
+    // 管理器发出的时间，用于统计广播延迟
+    pub sent_at: Instant,
 }
 
 impl Handler<BroadcastMessage> for WebSocketSession {
@@ -295,7 +1381,235 @@ impl Handler<BroadcastMessage> for WebSocketSession {
         if let Some(room_id) = &self.room_id {
             if room_id == &msg.room_id {
                 self.send_message(msg.message, ctx);
+                self.metrics.fanout_latency.observe(msg.sent_at.elapsed().as_secs_f64());
             }
         }
     }
-}
\ No newline at end of file
+}
+
+// 通知客户端后关闭连接
+#[derive(Message)]
+#[rtype(result = "()")]
+pub struct Disconnect {
+    pub message: ServerMessage,
+}
+
+impl Handler<Disconnect> for WebSocketSession {
+    type Result = ();
+
+    fn handle(&mut self, msg: Disconnect, ctx: &mut Self::Context) {
+        tracing::info!(parent: &self.span, "被服务器断开连接");
+        self.leaving = true;
+        self.send_message(msg.message, ctx);
+        ctx.close(Some(ws::CloseCode::Normal.into()));
+        ctx.stop();
+    }
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn message(seq: u64) -> ChatMessage {
+        ChatMessage {
+            id: format!("message-{}", seq),
+            seq,
+            content: format!("m{}", seq),
+            username: "alice".to_string(),
+            timestamp: chrono::Utc::now(),
+            user_id: "alice".to_string(),
+            edited_at: None,
+            reply_to: None,
+            reactions: Vec::new(),
+        }
+    }
+
+    fn summary(message: &ChatMessage) -> Vec<(&str, usize)> {
+        message.reactions.iter().map(|reaction| (reaction.emoji.as_str(), reaction.count)).collect()
+    }
+
+    fn manager() -> WebSocketManager {
+        WebSocketManager::new(Arc::new(Metrics::new()))
+    }
+
+    // 不启动会话的地址，只用于比较身份，发送的消息被丢弃
+    fn session_addr() -> Addr<WebSocketSession> {
+        let (sender, _) = actix::dev::channel::channel(16);
+        Addr::new(sender)
+    }
+
+    fn seqs(messages: &[ChatMessage]) -> Vec<u64> {
+        messages.iter().map(|message| message.seq).collect()
+    }
+
+    fn edited(seq: u64, content: &str) -> ServerMessage {
+        ServerMessage::MessageEdited {
+            id: format!("message-{}", seq),
+            seq,
+            content: content.to_string(),
+            edited_at: chrono::Utc::now(),
+        }
+    }
+
+    fn reacted(seq: u64) -> ServerMessage {
+        ServerMessage::ReactionsUpdated { message_id: format!("message-{}", seq), seq, reactions: Vec::new() }
+    }
+
+    fn deleted(seq: u64) -> ServerMessage {
+        ServerMessage::MessageDeleted { id: format!("message-{}", seq), seq }
+    }
+
+    fn broadcast_change(manager: &mut WebSocketManager, event: ServerMessage) {
+        let seq = match &event {
+            ServerMessage::MessageEdited { seq, .. }
+            | ServerMessage::MessageDeleted { seq, .. }
+            | ServerMessage::ReactionsUpdated { seq, .. } => *seq,
+            _ => unreachable!(),
+        };
+        manager.broadcast_change("room", &format!("message-{}", seq), seq, event);
+    }
+
+    // 事件类型、消息序号和编辑后的内容
+    fn describe(events: &[ServerMessage]) -> Vec<(&'static str, u64, &str)> {
+        events.iter().map(|event| match event {
+            ServerMessage::MessageEdited { seq, content, .. } => ("edited", *seq, content.as_str()),
+            ServerMessage::MessageDeleted { seq, .. } => ("deleted", *seq, ""),
+            ServerMessage::ReactionsUpdated { seq, .. } => ("reacted", *seq, ""),
+            _ => unreachable!(),
+        }).collect()
+    }
+
+    #[test]
+    fn keeps_latest_change_of_each_kind() {
+        let mut manager = manager();
+        broadcast_change(&mut manager, edited(1, "first"));
+        broadcast_change(&mut manager, reacted(1));
+        broadcast_change(&mut manager, edited(2, "other"));
+        broadcast_change(&mut manager, edited(1, "second"));
+        broadcast_change(&mut manager, reacted(1));
+
+        assert_eq!(
+            describe(&manager.changes_until("room", 2)),
+            [("edited", 2, "other"), ("edited", 1, "second"), ("reacted", 1, "")],
+        );
+    }
+
+    #[test]
+    fn deletion_replaces_earlier_changes() {
+        let mut manager = manager();
+        broadcast_change(&mut manager, edited(1, "first"));
+        broadcast_change(&mut manager, reacted(1));
+        broadcast_change(&mut manager, reacted(2));
+        broadcast_change(&mut manager, deleted(1));
+
+        assert_eq!(describe(&manager.changes_until("room", 2)), [("reacted", 2, ""), ("deleted", 1, "")]);
+    }
+
+    #[test]
+    fn changes_until_skips_newer_messages() {
+        let mut manager = manager();
+        for seq in 1..=3 {
+            broadcast_change(&mut manager, reacted(seq));
+        }
+        assert_eq!(describe(&manager.changes_until("room", 2)), [("reacted", 1, ""), ("reacted", 2, "")]);
+        assert!(manager.changes_until("room", 0).is_empty());
+        assert!(manager.changes_until("other", 3).is_empty());
+    }
+
+    #[test]
+    fn resumes_detached_session_once() {
+        let mut manager = manager();
+        let first = session_addr();
+        manager.add_session("alice".to_string(), "room".to_string(), "Alice".to_string(), first.clone());
+        let token = manager.issue_resume_token("alice", "room", "Alice");
+
+        // 已被其他连接接管的会话不会被旧连接断开
+        assert!(!manager.detach_session("alice", "room", "Alice", &session_addr()));
+        assert!(manager.detach_session("alice", "room", "Alice", &first));
+        assert!(!manager.is_member("room", "alice"));
+        assert!(manager.is_username_taken("room", "alice"));
+        assert!(!manager.expire_detached("alice"));
+
+        let second = session_addr();
+        let entry = manager.resume_session(&token, second.clone()).unwrap();
+        assert_eq!((entry.user_id.as_str(), entry.room_id.as_str(), entry.username.as_str()), ("alice", "room", "Alice"));
+        assert!(manager.is_member("room", "alice"));
+        assert!(manager.detached.is_empty());
+        assert!(manager.sessions["alice"] == second);
+        // 令牌只能使用一次
+        assert!(manager.resume_session(&token, session_addr()).is_none());
+    }
+
+    #[test]
+    fn expires_detached_session_after_grace() {
+        let mut manager = manager();
+        let addr = session_addr();
+        manager.add_session("alice".to_string(), "room".to_string(), "Alice".to_string(), addr.clone());
+        let token = manager.issue_resume_token("alice", "room", "Alice");
+        assert!(manager.detach_session("alice", "room", "Alice", &addr));
+
+        manager.detached.get_mut("alice").unwrap().since -= RESUME_GRACE;
+        assert!(manager.expire_detached("alice"));
+        assert!(!manager.expire_detached("alice"));
+        assert_eq!(manager.user_count("room"), 0);
+        assert!(!manager.is_username_taken("room", "Alice"));
+        assert!(manager.resume_session(&token, session_addr()).is_none());
+    }
+
+    #[test]
+    fn replays_buffered_messages_after_seq() {
+        let mut manager = manager();
+        for seq in 1..=3 {
+            manager.broadcast_chat("room", message(seq));
+        }
+        assert_eq!(seqs(&manager.replay_since("room", 1)), [2, 3]);
+        assert!(manager.replay_since("room", 3).is_empty());
+        assert!(manager.replay_since("other", 0).is_empty());
+
+        // 缓冲只保留最近的消息
+        for seq in 4..=REPLAY_BUFFER_SIZE as u64 + 5 {
+            manager.broadcast_chat("room", message(seq));
+        }
+        let replay = manager.replay_since("room", 0);
+        assert_eq!(replay.len(), REPLAY_BUFFER_SIZE);
+        assert_eq!(replay[0].seq, 6);
+    }
+
+    #[test]
+    fn reactions_count_each_user_once() {
+        let mut message = message(1);
+        assert!(message.react("alice", "👍").unwrap());
+        assert!(!message.react("alice", " 👍 ").unwrap());
+        assert!(message.react("bob", "👍").unwrap());
+        assert!(message.react("bob", "🎉").unwrap());
+        assert_eq!(summary(&message), [("👍", 2), ("🎉", 1)]);
+
+        assert!(message.unreact("alice", "👍"));
+        assert!(!message.unreact("alice", "👍"));
+        assert!(!message.unreact("alice", "🎉"));
+        assert!(message.unreact("bob", "🎉"));
+        // 没有人回应的表情被移除
+        assert_eq!(summary(&message), [("👍", 1)]);
+        assert_eq!(message.reactions[0].user_ids, ["bob"]);
+    }
+
+    #[test]
+    fn rejects_invalid_reactions() {
+        let mut message = message(1);
+        for emoji in ["", "  ", "lol", "\" onclick=x", "👍👍"] {
+            assert!(matches!(message.react("alice", emoji), Err(ChatError::InvalidEmoji)), "{:?}", emoji);
+        }
+        assert!(message.reactions.is_empty());
+    }
+
+    #[test]
+    fn limits_distinct_reactions_per_message() {
+        let mut message = message(1);
+        let emojis: Vec<String> = ('\u{1F600}'..).take(MAX_REACTIONS_PER_MESSAGE + 1).map(String::from).collect();
+        for emoji in &emojis[..MAX_REACTIONS_PER_MESSAGE] {
+            assert!(message.react("alice", emoji).unwrap());
+        }
+        assert!(matches!(message.react("alice", &emojis[MAX_REACTIONS_PER_MESSAGE]), Err(ChatError::TooManyReactions)));
+        // 已有的表情仍然可以继续回应
+        assert!(message.react("bob", &emojis[0]).unwrap());
+    }
+}
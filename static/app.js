@@ -1,8 +1,26 @@
 class ChatApp {
     constructor() {
         this.ws = null;
+        this.resumeToken = null;
+        // 正在用恢复令牌重连，失败时改为重新加入
+        this.resuming = false;
+        // 加入时使用的房间、昵称和密码，恢复失败后重新申请入场券，只保存在内存中
+        this.credentials = null;
+        this.lastSeq = null;
+        this.reconnectAttempts = 0;
+        // 服务器重启时指定的重连等待时间
+        this.reconnectDelay = null;
         this.currentRoom = null;
         this.currentUser = null;
+        this.userId = null;
+        this.members = [];
+        this.typingUsers = new Map();
+        this.readSeqs = new Map();
+        this.lastTypingSent = 0;
+        this.lastReadSent = 0;
+        this.readTimer = null;
+        this.replyTo = null;
+        this.directTo = null;
         this.messages = [];
         this.init();
     }
@@ -41,6 +59,47 @@ class ChatApp {
             }
         });
 
+        // 正在输入提示
+        document.getElementById('message-input').addEventListener('input', (e) => {
+            this.notifyTyping(e.target.value.trim() !== '');
+        });
+
+        // 回复、表情回应，以及编辑、删除自己的消息
+        document.getElementById('chat-messages').addEventListener('click', (e) => {
+            const button = e.target.closest('[data-action]');
+            if (!button) {
+                return;
+            }
+            const messageId = button.closest('.message').dataset.id;
+            switch (button.dataset.action) {
+                case 'reply':
+                    this.setReplyTo(messageId);
+                    break;
+                case 'react':
+                    this.toggleReaction(messageId, button.dataset.emoji);
+                    break;
+                case 'edit':
+                    this.showEditMessageModal(messageId);
+                    break;
+                case 'delete':
+                    this.deleteMessage(messageId);
+                    break;
+            }
+        });
+
+        document.getElementById('cancel-reply-btn').addEventListener('click', () => {
+            this.setReplyTo(null);
+            this.setDirectTo(null);
+        });
+
+        // 点击成员昵称发送私信
+        document.getElementById('chat-members').addEventListener('click', (e) => {
+            const member = e.target.closest('[data-user-id]');
+            if (member) {
+                this.setDirectTo(member.dataset.userId);
+            }
+        });
+
         // 离开聊天室
         document.getElementById('leave-room-btn').addEventListener('click', () => {
             this.leaveRoom();
@@ -51,6 +110,11 @@ class ChatApp {
             this.showUpdatePasswordModal();
         });
 
+        // 删除聊天室（仅房主）
+        document.getElementById('delete-room-btn').addEventListener('click', () => {
+            this.deleteRoom();
+        });
+
         // 模态框事件
         document.querySelector('.close').addEventListener('click', () => {
             this.hideModal();
@@ -71,6 +135,8 @@ class ChatApp {
     async createRoom() {
         const name = document.getElementById('room-name').value.trim();
         const password = document.getElementById('room-password').value.trim();
+        const history = document.getElementById('room-history').checked;
+        const maxMembers = parseInt(document.getElementById('room-max-members').value, 10);
 
         if (!name) {
             this.showNotification('请输入聊天室名称', 'error');
@@ -85,7 +151,9 @@ class ChatApp {
                 },
                 body: JSON.stringify({
                     name: name,
-                    password: password || null
+                    password: password || null,
+                    history: history,
+                    max_members: Number.isNaN(maxMembers) ? null : maxMembers
                 })
             });
 
@@ -93,8 +161,11 @@ class ChatApp {
 
             if (result.success) {
                 this.showNotification('聊天室创建成功！', 'success');
+                this.saveOwnerToken(result.data.room_id, result.data.owner_token);
                 document.getElementById('room-name').value = '';
                 document.getElementById('room-password').value = '';
+                document.getElementById('room-history').checked = false;
+                document.getElementById('room-max-members').value = '';
                 document.getElementById('join-room-id').value = result.data.room_id;
                 this.loadRooms();
             } else {
@@ -122,26 +193,19 @@ class ChatApp {
         }
 
         try {
-            const response = await fetch('/api/rooms/join', {
-                method: 'POST',
-                headers: {
-                    'Content-Type': 'application/json',
-                },
-                body: JSON.stringify({
-                    room_id: roomId,
-                    password: password || null
-                })
-            });
-
-            const result = await response.json();
+            const result = await this.requestTicket(roomId, username, password);
 
             if (result.success) {
+                this.credentials = { roomId, username, password };
                 this.currentRoom = {
                     id: roomId,
                     name: result.data.room_name
                 };
                 this.currentUser = username;
-                this.connectWebSocket();
+                this.resumeToken = null;
+                this.lastSeq = null;
+                // 凭入场券加入房间，不再通过 WebSocket 发送密码
+                this.connectWebSocket({ type: 'join', ticket: result.data.ticket });
                 this.showChatPage();
                 this.loadMessagesFromStorage();
             } else {
@@ -153,55 +217,224 @@ class ChatApp {
         }
     }
 
-    connectWebSocket() {
+    async requestTicket(roomId, username, password) {
+        const response = await fetch('/api/rooms/join', {
+            method: 'POST',
+            headers: {
+                'Content-Type': 'application/json',
+            },
+            body: JSON.stringify({
+                room_id: roomId,
+                username: username,
+                password: password || null
+            })
+        });
+        return response.json();
+    }
+
+    // 会话无法恢复（例如服务器重启后恢复令牌失效）时重新申请入场券加入，房间已不存在则返回首页
+    async rejoin() {
+        const credentials = this.credentials;
+        if (!credentials) {
+            return;
+        }
+
+        try {
+            const result = await this.requestTicket(credentials.roomId, credentials.username, credentials.password);
+            // 等待期间用户已离开或加入了其他房间
+            if (this.credentials !== credentials) {
+                return;
+            }
+            if (!result.success) {
+                this.showNotification(result.message || '重新加入失败', 'error');
+                this.leaveRoom();
+                return;
+            }
+
+            this.currentRoom.name = result.data.room_name;
+            document.getElementById('chat-room-name').textContent = result.data.room_name;
+            // 重启后不保存历史的房间序号从头开始
+            this.lastSeq = null;
+            this.lastReadSent = 0;
+            const joinMessage = { type: 'join', ticket: result.data.ticket };
+            if (this.ws && this.ws.readyState === WebSocket.OPEN) {
+                this.ws.send(JSON.stringify(joinMessage));
+            } else {
+                this.connectWebSocket(joinMessage);
+            }
+        } catch (error) {
+            this.showNotification('网络错误，请重新加入聊天室', 'error');
+            console.error('Error rejoining room:', error);
+        }
+    }
+
+    connectWebSocket(joinMessage) {
         const protocol = window.location.protocol === 'https:' ? 'wss:' : 'ws:';
         const wsUrl = `${protocol}//${window.location.host}/ws`;
         
-        this.ws = new WebSocket(wsUrl);
+        const ws = new WebSocket(wsUrl);
+        this.ws = ws;
 
-        this.ws.onopen = () => {
+        ws.onopen = () => {
             console.log('WebSocket connected');
-            // 发送加入房间消息
-            this.ws.send(JSON.stringify({
-                type: 'join',
-                room_id: this.currentRoom.id,
-                username: this.currentUser,
-                password: document.getElementById('join-password').value.trim() || null
-            }));
+            ws.send(JSON.stringify(joinMessage));
         };
 
-        this.ws.onmessage = (event) => {
+        ws.onmessage = (event) => {
             const message = JSON.parse(event.data);
             this.handleWebSocketMessage(message);
         };
 
-        this.ws.onclose = () => {
+        ws.onclose = () => {
             console.log('WebSocket disconnected');
+            // 主动离开时 this.ws 已被清空，不再重连
+            if (this.ws !== ws) {
+                return;
+            }
+
+            if (this.resumeToken && this.reconnectAttempts < 5) {
+                this.reconnectAttempts++;
+                const delay = this.reconnectDelay ?? 1000 * this.reconnectAttempts;
+                this.reconnectDelay = null;
+                setTimeout(() => this.resumeSession(), delay);
+                return;
+            }
             this.showNotification('连接已断开', 'error');
         };
 
-        this.ws.onerror = (error) => {
+        ws.onerror = (error) => {
             console.error('WebSocket error:', error);
             this.showNotification('连接错误', 'error');
         };
     }
 
+    // 断线后用恢复令牌接回原来的身份，服务器会补发错过的消息
+    resumeSession() {
+        if (!this.currentRoom || !this.resumeToken) {
+            return;
+        }
+
+        this.resuming = true;
+        this.connectWebSocket({
+            type: 'resume',
+            resume_token: this.resumeToken,
+            last_seq: this.lastSeq || 0
+        });
+    }
+
+    trackSeq(seq) {
+        if (typeof seq === 'number' && (this.lastSeq === null || seq > this.lastSeq)) {
+            this.lastSeq = seq;
+        }
+    }
+
     handleWebSocketMessage(message) {
         switch (message.type) {
             case 'chat':
+                this.trackSeq(message.seq);
+                this.clearTyping(message.user_id);
                 this.addMessage(message);
+                this.markRead();
+                break;
+            case 'direct':
+                this.addDirectMessage(message);
+                break;
+            case 'history':
+                message.messages.forEach(m => this.trackSeq(m.seq));
+                this.mergeHistory(message.messages);
+                this.markRead();
+                break;
+            case 'members':
+                this.members = message.members;
+                this.renderMembers();
                 break;
             case 'user_joined':
+                this.members.push({
+                    user_id: message.user_id,
+                    username: message.username,
+                    joined_at: message.timestamp
+                });
+                this.renderMembers();
                 this.addSystemMessage(`${message.username} 加入了聊天室`);
                 break;
             case 'user_left':
+                this.members = this.members.filter(m => m.user_id !== message.user_id);
+                this.renderMembers();
+                this.clearTyping(message.user_id);
+                if (this.directTo === message.user_id) {
+                    this.setDirectTo(null);
+                }
                 this.addSystemMessage(`${message.username} 离开了聊天室`);
                 break;
+            case 'message_edited':
+                this.applyEdit(message.id, message.content, message.edited_at);
+                break;
+            case 'message_deleted':
+                this.applyDelete(message.id);
+                break;
+            case 'reactions_updated':
+                this.applyReactions(message.message_id, message.reactions);
+                break;
+            case 'typing_start':
+                clearTimeout(this.typingUsers.get(message.user_id)?.timer);
+                // 对方持续输入时会定期重发，超时未收到则视为停止
+                this.typingUsers.set(message.user_id, {
+                    username: message.username,
+                    timer: setTimeout(() => this.clearTyping(message.user_id), 5000)
+                });
+                this.renderTyping();
+                break;
+            case 'typing_stop':
+                this.clearTyping(message.user_id);
+                break;
+            case 'read_up_to':
+                this.readSeqs.set(message.user_id, message.seq);
+                this.renderReadReceipts();
+                break;
             case 'error':
+                // 恢复令牌已失效或服务器已重启，改为重新加入
+                if (this.resuming && (message.code === 'SESSION_EXPIRED' || message.code === 'ROOM_NOT_FOUND')) {
+                    this.resuming = false;
+                    this.resumeToken = null;
+                    this.rejoin();
+                    break;
+                }
                 this.showNotification(message.message, 'error');
                 break;
             case 'joined':
-                this.showNotification('成功加入聊天室！', 'success');
+                if (this.reconnectAttempts > 0) {
+                    this.showNotification('已重新连接', 'success');
+                } else {
+                    this.showNotification('成功加入聊天室！', 'success');
+                }
+                this.reconnectAttempts = 0;
+                this.resuming = false;
+                this.userId = message.user_id;
+                this.resumeToken = message.resume_token;
+                this.trackSeq(message.last_seq);
+                this.markRead();
+                break;
+            case 'room_renamed':
+                this.currentRoom.name = message.name;
+                document.getElementById('chat-room-name').textContent = message.name;
+                this.addSystemMessage(`聊天室已重命名为 ${message.name}`);
+                break;
+            case 'room_closed':
+                this.showNotification('聊天室已被房主删除', 'error');
+                this.leaveRoom();
+                break;
+            case 'kicked':
+                this.showNotification('您已被房主移出聊天室', 'error');
+                this.leaveRoom();
+                break;
+            case 'server_shutdown':
+                this.reconnectDelay = message.reconnect_after_ms;
+                this.showNotification('服务器正在重启，稍后自动重连', 'info');
+                break;
+            case 'ownership_granted':
+                this.saveOwnerToken(message.room_id, message.owner_token);
+                this.updateOwnerControls();
+                this.showNotification('您已成为房主', 'success');
                 break;
         }
     }
@@ -209,7 +442,7 @@ class ChatApp {
     addMessage(message) {
         const messageData = {
             ...message,
-            isOwn: message.username === this.currentUser
+            isOwn: message.user_id === this.userId
         };
         
         this.messages.push(messageData);
@@ -218,6 +451,240 @@ class ChatApp {
         this.scrollToBottom();
     }
 
+    // 合并服务器端历史，跳过本地已有的消息
+    mergeHistory(history) {
+        const known = new Set(this.messages.filter(m => m.id).map(m => m.id));
+        const missing = history.filter(m => !known.has(m.id));
+
+        if (missing.length === 0) {
+            return;
+        }
+
+        missing.forEach(message => {
+            this.messages.push({
+                ...message,
+                type: 'chat',
+                isOwn: message.user_id === this.userId
+            });
+        });
+        this.messages.sort((a, b) => new Date(a.timestamp) - new Date(b.timestamp));
+        this.saveMessagesToStorage();
+        this.showChatPage();
+    }
+
+    renderMembers() {
+        const names = this.members.map(m => m.user_id === this.userId
+            ? this.escapeHtml(m.username)
+            : `<button class="btn-link" data-user-id="${this.escapeHtml(m.user_id)}" title="发送私信">${this.escapeHtml(m.username)}</button>`
+        ).join('、');
+        document.getElementById('chat-members').innerHTML = `成员 (${this.members.length}): ${names}`;
+    }
+
+    // 私信对象在取消前一直有效，与回复互斥
+    setDirectTo(userId) {
+        const member = userId && this.members.find(m => m.user_id === userId);
+        this.directTo = member ? member.user_id : null;
+        if (member) {
+            this.replyTo = null;
+            document.getElementById('message-input').focus();
+        }
+        this.renderComposeBar();
+    }
+
+    // 输入框上方显示正在回复的消息或私信对象
+    renderComposeBar() {
+        const reply = this.replyTo && this.messages.find(m => m.id === this.replyTo);
+        const member = this.directTo && this.members.find(m => m.user_id === this.directTo);
+        let text = '';
+        if (reply) {
+            text = `回复 ${reply.username}: ${reply.content}`;
+        } else if (member) {
+            text = `私信 ${member.username}`;
+        }
+        document.getElementById('reply-bar').style.display = text ? '' : 'none';
+        document.getElementById('reply-preview').textContent = text;
+    }
+
+    addDirectMessage(message) {
+        const isOwn = message.from_user_id === this.userId;
+        const peer = isOwn
+            ? this.members.find(m => m.user_id === message.to_user_id)?.username || '对方'
+            : null;
+        this.addMessage({
+            ...message,
+            type: 'direct',
+            username: isOwn ? `私信给 ${peer}` : `${message.from_username} 的私信`,
+            user_id: message.from_user_id
+        });
+    }
+
+    setReplyTo(messageId) {
+        const message = messageId && this.messages.find(m => m.id === messageId && m.type !== 'direct');
+        this.replyTo = message ? message.id : null;
+        if (message) {
+            this.directTo = null;
+            document.getElementById('message-input').focus();
+        }
+        this.renderComposeBar();
+    }
+
+    renderReplyQuote(replyTo) {
+        if (!replyTo) {
+            return '';
+        }
+        const original = this.messages.find(m => m.id === replyTo);
+        const text = original ? `${original.username}: ${original.content}` : '原消息已不可见';
+        return `<div class="message-reply">${this.escapeHtml(text)}</div>`;
+    }
+
+    renderReactions(reactions) {
+        if (!reactions || reactions.length === 0) {
+            return '';
+        }
+        const chips = reactions.map(reaction => {
+            const mine = reaction.user_ids.includes(this.userId);
+            return `<button class="reaction ${mine ? 'mine' : ''}" data-action="react" data-emoji="${this.escapeHtml(reaction.emoji)}">${this.escapeHtml(reaction.emoji)} ${reaction.count}</button>`;
+        }).join('');
+        return `<div class="message-reactions">${chips}</div>`;
+    }
+
+    // 已回应过该表情则取消，否则添加
+    toggleReaction(messageId, emoji) {
+        const message = this.messages.find(m => m.id === messageId);
+        const reacted = (message?.reactions || [])
+            .some(reaction => reaction.emoji === emoji && reaction.user_ids.includes(this.userId));
+        this.sendIfConnected({ type: reacted ? 'unreact' : 'react', message_id: messageId, emoji: emoji });
+    }
+
+    applyReactions(messageId, reactions) {
+        const message = this.messages.find(m => m.id === messageId);
+        if (!message) {
+            return;
+        }
+        message.reactions = reactions;
+        this.saveMessagesToStorage();
+        this.showChatPage();
+    }
+
+    showEditMessageModal(messageId) {
+        const message = this.messages.find(m => m.id === messageId);
+        if (!message) {
+            return;
+        }
+
+        document.getElementById('modal-title').textContent = '编辑消息';
+        document.getElementById('modal-body').innerHTML = `
+            <input type="text" id="edit-message-content" maxlength="500" style="width: 100%;">
+        `;
+        document.getElementById('edit-message-content').value = message.content;
+
+        document.getElementById('modal-confirm').onclick = () => {
+            const content = document.getElementById('edit-message-content').value.trim();
+            if (content && content !== message.content) {
+                this.sendIfConnected({ type: 'edit_message', id: messageId, content: content });
+            }
+            this.hideModal();
+        };
+
+        this.showModal();
+    }
+
+    deleteMessage(messageId) {
+        if (confirm('确定要删除这条消息吗？')) {
+            this.sendIfConnected({ type: 'delete_message', id: messageId });
+        }
+    }
+
+    sendIfConnected(payload) {
+        if (!this.ws || this.ws.readyState !== WebSocket.OPEN) {
+            this.showNotification('连接已断开，请重新加入聊天室', 'error');
+            return;
+        }
+        this.ws.send(JSON.stringify(payload));
+    }
+
+    applyEdit(messageId, content, editedAt) {
+        const message = this.messages.find(m => m.id === messageId);
+        if (!message) {
+            return;
+        }
+        message.content = content;
+        message.edited_at = editedAt;
+        this.saveMessagesToStorage();
+        this.showChatPage();
+    }
+
+    applyDelete(messageId) {
+        const count = this.messages.length;
+        this.messages = this.messages.filter(m => m.id !== messageId);
+        if (this.messages.length !== count) {
+            this.saveMessagesToStorage();
+            this.showChatPage();
+        }
+    }
+
+    notifyTyping(typing) {
+        if (!this.ws || this.ws.readyState !== WebSocket.OPEN) {
+            return;
+        }
+
+        const now = Date.now();
+        if (typing) {
+            // 服务器对正在输入通知限流，这里按相同间隔重发
+            if (now - this.lastTypingSent < 2000) {
+                return;
+            }
+            this.lastTypingSent = now;
+            this.ws.send(JSON.stringify({ type: 'typing_start' }));
+        } else if (this.lastTypingSent) {
+            this.lastTypingSent = 0;
+            this.ws.send(JSON.stringify({ type: 'typing_stop' }));
+        }
+    }
+
+    clearTyping(userId) {
+        const entry = this.typingUsers.get(userId);
+        if (entry) {
+            clearTimeout(entry.timer);
+            this.typingUsers.delete(userId);
+            this.renderTyping();
+        }
+    }
+
+    renderTyping() {
+        const names = [...this.typingUsers.values()].map(entry => entry.username);
+        document.getElementById('typing-indicator').textContent =
+            names.length ? `${names.join('、')} 正在输入...` : '';
+    }
+
+    // 告诉其他成员已读到的最新消息，一秒内收到的多条消息只发送一次
+    markRead() {
+        if (this.readTimer) {
+            return;
+        }
+        this.readTimer = setTimeout(() => {
+            this.readTimer = null;
+            if (!this.ws || this.ws.readyState !== WebSocket.OPEN || this.lastSeq === null) {
+                return;
+            }
+            if (this.lastSeq > this.lastReadSent) {
+                this.lastReadSent = this.lastSeq;
+                this.ws.send(JSON.stringify({ type: 'read_up_to', seq: this.lastSeq }));
+            }
+        }, 1000);
+    }
+
+    // 在自己的消息下方显示已读人数
+    renderReadReceipts() {
+        document.querySelectorAll('#chat-messages .message.own[data-seq]').forEach(element => {
+            const seq = Number(element.dataset.seq);
+            const count = [...this.readSeqs.entries()]
+                .filter(([userId, readSeq]) => userId !== this.userId && readSeq >= seq)
+                .length;
+            element.querySelector('.message-read').textContent = count ? `已读 ${count}` : '';
+        });
+    }
+
     addSystemMessage(content) {
         const messageData = {
             type: 'system',
@@ -242,13 +709,30 @@ class ChatApp {
                 <div class="message-content">${this.escapeHtml(message.content)}</div>
             `;
         } else {
-            messageElement.className = `message ${message.isOwn ? 'own' : 'other'}`;
+            messageElement.className = `message ${message.isOwn ? 'own' : 'other'}${message.type === 'direct' ? ' direct' : ''}`;
             const time = new Date(message.timestamp).toLocaleTimeString();
+            if (message.seq) {
+                messageElement.dataset.seq = message.seq;
+            }
+            if (message.id) {
+                messageElement.dataset.id = message.id;
+            }
+            const actions = message.id && message.type !== 'direct' ? `
+                <span class="message-actions">
+                    <button class="btn-link" data-action="reply">回复</button>
+                    <button class="btn-link" data-action="react" data-emoji="👍">👍</button>
+                    ${message.isOwn ? `
+                    <button class="btn-link" data-action="edit">编辑</button>
+                    <button class="btn-link" data-action="delete">删除</button>` : ''}
+                </span>` : '';
             
             messageElement.innerHTML = `
+                ${this.renderReplyQuote(message.reply_to)}
                 <div class="message-header">${this.escapeHtml(message.username)}</div>
                 <div class="message-content">${this.escapeHtml(message.content)}</div>
-                <div class="message-time">${time}</div>
+                ${this.renderReactions(message.reactions)}
+                <div class="message-time">${message.edited_at ? '（已编辑）' : ''}${time}${actions}</div>
+                ${message.isOwn ? '<div class="message-read"></div>' : ''}
             `;
         }
         
@@ -268,24 +752,53 @@ class ChatApp {
             return;
         }
 
-        this.ws.send(JSON.stringify({
-            type: 'chat',
-            content: content,
-            username: this.currentUser
-        }));
+        if (this.directTo) {
+            this.ws.send(JSON.stringify({
+                type: 'direct',
+                to_user_id: this.directTo,
+                content: content
+            }));
+        } else {
+            this.ws.send(JSON.stringify({
+                type: 'chat',
+                content: content,
+                reply_to: this.replyTo
+            }));
+        }
 
-        // 不在这里立即显示消息，等待服务器广播回来
+        // 不在这里立即显示消息，等待服务器广播或回显
         input.value = '';
+        this.setReplyTo(null);
+        this.lastTypingSent = 0;
     }
 
     leaveRoom() {
         if (this.ws) {
-            this.ws.close();
+            const ws = this.ws;
             this.ws = null;
+            ws.close();
         }
+        this.resumeToken = null;
+        this.resuming = false;
+        this.credentials = null;
+        this.lastSeq = null;
+        this.reconnectAttempts = 0;
+        this.reconnectDelay = null;
         
         this.currentRoom = null;
         this.currentUser = null;
+        this.members = [];
+        this.renderMembers();
+        this.typingUsers.forEach(entry => clearTimeout(entry.timer));
+        this.typingUsers.clear();
+        this.renderTyping();
+        this.readSeqs.clear();
+        this.lastTypingSent = 0;
+        this.lastReadSent = 0;
+        clearTimeout(this.readTimer);
+        this.readTimer = null;
+        this.setReplyTo(null);
+        this.setDirectTo(null);
         this.showHomePage();
         this.clearForm();
     }
@@ -294,6 +807,7 @@ class ChatApp {
         document.getElementById('home-page').classList.remove('active');
         document.getElementById('chat-page').classList.add('active');
         document.getElementById('chat-room-name').textContent = this.currentRoom.name;
+        this.updateOwnerControls();
         
         // 清空并重新渲染消息
         const messagesContainer = document.getElementById('chat-messages');
@@ -302,10 +816,40 @@ class ChatApp {
         this.messages.forEach(message => {
             this.renderMessage(message);
         });
+        this.renderReadReceipts();
         
         this.scrollToBottom();
     }
 
+    updateOwnerControls() {
+        const isOwner = this.currentRoom && !!this.getOwnerToken(this.currentRoom.id);
+        document.getElementById('delete-room-btn').style.display = isOwner ? '' : 'none';
+    }
+
+    async deleteRoom() {
+        if (!this.currentRoom || !confirm('确定要删除该聊天室吗？所有用户都将被断开')) {
+            return;
+        }
+
+        try {
+            const response = await fetch(`/api/rooms/${this.currentRoom.id}`, {
+                method: 'DELETE',
+                headers: {
+                    'Authorization': `Bearer ${this.getOwnerToken(this.currentRoom.id)}`
+                }
+            });
+
+            const result = await response.json();
+
+            if (!result.success) {
+                this.showNotification(result.message || '删除失败', 'error');
+            }
+        } catch (error) {
+            this.showNotification('网络错误，请重试', 'error');
+            console.error('Error deleting room:', error);
+        }
+    }
+
     showHomePage() {
         document.getElementById('chat-page').classList.remove('active');
         document.getElementById('home-page').classList.add('active');
@@ -347,7 +891,7 @@ class ChatApp {
                     <h4>${this.escapeHtml(room.name)}</h4>
                     <div class="room-meta">
                         ID: ${room.id} | 
-                        用户: ${room.user_count} | 
+                        用户: ${room.user_count}/${room.max_members} | 
                         ${room.has_password ? '<span class="password-indicator">🔒 需要密码</span>' : '🔓 无密码'} |
                         创建时间: ${new Date(room.created_at).toLocaleString()}
                     </div>
@@ -372,8 +916,10 @@ class ChatApp {
         }
 
         document.getElementById('modal-title').textContent = '修改聊天室密码';
+        const isOwner = !!this.getOwnerToken(this.currentRoom.id);
         document.getElementById('modal-body').innerHTML = `
-            <input type="password" id="new-password" placeholder="新密码" style="width: 100%;">
+            ${isOwner ? '' : '<input type="password" id="current-password" placeholder="当前密码" style="width: 100%;">'}
+            <input type="password" id="new-password" placeholder="新密码（留空表示移除密码）" style="width: 100%;">
             <small style="color: #7f8c8d;">注意：修改密码后，新用户需要使用新密码才能加入</small>
         `;
 
@@ -386,21 +932,25 @@ class ChatApp {
 
     async updatePassword() {
         const newPassword = document.getElementById('new-password').value.trim();
+        const currentPasswordInput = document.getElementById('current-password');
+        const currentPassword = currentPasswordInput ? currentPasswordInput.value.trim() : '';
 
-        if (!newPassword) {
-            this.showNotification('密码不能为空', 'error');
+        if (!newPassword && !confirm('确定要移除密码，将聊天室设为公开吗？')) {
             return;
         }
 
         try {
+            const ownerToken = this.getOwnerToken(this.currentRoom.id);
             const response = await fetch('/api/rooms/password', {
                 method: 'POST',
                 headers: {
                     'Content-Type': 'application/json',
+                    ...(ownerToken ? { 'Authorization': `Bearer ${ownerToken}` } : {})
                 },
                 body: JSON.stringify({
                     room_id: this.currentRoom.id,
-                    new_password: newPassword
+                    new_password: newPassword || null,
+                    current_password: currentPassword || null
                 })
             });
 
@@ -443,10 +993,19 @@ class ChatApp {
         messagesContainer.scrollTop = messagesContainer.scrollHeight;
     }
 
+    // 结果也会放进属性值，所以引号同样需要转义
     escapeHtml(text) {
-        const div = document.createElement('div');
-        div.textContent = text;
-        return div.innerHTML;
+        const entities = { '&': '&amp;', '<': '&lt;', '>': '&gt;', '"': '&quot;', "'": '&#39;' };
+        return String(text ?? '').replace(/[&<>"']/g, c => entities[c]);
+    }
+
+    // 房主令牌只在创建时返回一次，保存在本地
+    saveOwnerToken(roomId, token) {
+        localStorage.setItem(`chatroom_owner_${roomId}`, token);
+    }
+
+    getOwnerToken(roomId) {
+        return localStorage.getItem(`chatroom_owner_${roomId}`);
     }
 
     // 本地存储消息